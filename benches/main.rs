@@ -1,20 +1,26 @@
 use chess_bench::{
     impls,
     perft::{self, Case},
-    Perft,
+    Error, Perft,
 };
 use criterion::{criterion_group, criterion_main, Criterion};
 
 fn do_perft(
     c: &mut Criterion,
     name: &'static str,
-    perfter: impl Fn(&Case, &(dyn Perft + 'static)),
+    perfter: impl Fn(&Case, &(dyn Perft + 'static)) -> Result<(), Error>,
 ) {
-    let perfts = impls::all_perft();
+    // Reference implementations are too slow, and would only skew the plots.
+    let impls: Vec<_> = impls::registry()
+        .into_iter()
+        .filter(|imp| !imp.reference_only)
+        .collect();
     for case in &perft::CASES {
         let mut group = c.benchmark_group(format!("{}/{}", name, case.name));
-        for p in &perfts {
-            group.bench_function(p.name(), |b| b.iter(|| perfter(case, &**p)));
+        for imp in &impls {
+            group.bench_function(imp.name, |b| {
+                b.iter(|| perfter(case, imp.perft.as_ref()).unwrap())
+            });
         }
     }
 }
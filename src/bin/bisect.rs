@@ -0,0 +1,50 @@
+use clap::Parser;
+
+use std::process;
+
+use chess_bench::{bisect, impls, Perft};
+
+#[derive(Parser)]
+#[clap(
+    name = "bisect",
+    version,
+    about = "Finds the position where two chess implementations disagree on perft"
+)]
+struct Cli {
+    #[clap(value_parser)]
+    #[clap(help = "First chess implementation name")]
+    first: String,
+
+    #[clap(value_parser)]
+    #[clap(help = "Second chess implementation name")]
+    second: String,
+
+    #[clap(value_parser)]
+    #[clap(help = "Position to start from")]
+    fen: String,
+
+    #[clap(value_parser)]
+    #[clap(help = "Perft depth")]
+    depth: usize,
+}
+
+fn find_perft(name: &str) -> Box<dyn Perft> {
+    impls::find_perft(name).unwrap_or_else(|| {
+        eprintln!("error: unknown implementation {}", name);
+        process::exit(1);
+    })
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let first = find_perft(&cli.first);
+    let second = find_perft(&cli.second);
+    match bisect::bisect(first.as_ref(), second.as_ref(), &cli.fen, cli.depth) {
+        Ok(Some(mismatch)) => println!("{}", mismatch),
+        Ok(None) => println!("no mismatch found"),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use clap::Parser;
+
+use std::process;
+
+use chess_bench::{consensus, impls, parallel::Parallel, Perft};
+
+#[derive(Parser)]
+#[clap(
+    name = "consensus",
+    version,
+    about = "Generates perft test cases on which all the chess implementations agree"
+)]
+struct Cli {
+    #[clap(value_parser, required = true)]
+    #[clap(help = "Positions to generate cases for")]
+    fens: Vec<String>,
+
+    #[clap(short, long)]
+    #[clap(help = "Perft depth")]
+    depth: usize,
+
+    #[clap(short, long)]
+    #[clap(help = "Case names, in the same order as positions (default: case_<N>)")]
+    name: Vec<String>,
+
+    #[clap(long, action)]
+    #[clap(help = "Emit EPD lines with results for all depths up to --depth instead of Rust code")]
+    epd: bool,
+
+    #[clap(short = 'j', long, default_value_t = 1)]
+    #[clap(help = "Number of threads")]
+    threads: usize,
+
+    #[clap(long, action)]
+    #[clap(help = "Also run the slow reference implementations (like mailbox)")]
+    with_reference: bool,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.threads == 0 {
+        eprintln!("error: need at least one thread");
+        process::exit(1);
+    }
+    let inner: Vec<_> = impls::registry()
+        .into_iter()
+        .filter(|imp| cli.with_reference || !imp.reference_only)
+        .map(|imp| imp.perft)
+        .collect();
+    let parallel: Vec<_> = inner
+        .iter()
+        .map(|p| Parallel::new(p.as_ref(), cli.threads))
+        .collect();
+    let perfts: Vec<&dyn Perft> = parallel.iter().map(|p| p as &dyn Perft).collect();
+
+    let mut failed = false;
+    for (idx, fen) in cli.fens.iter().enumerate() {
+        let result = if cli.epd {
+            consensus::epd_line(&perfts, fen, cli.depth)
+        } else {
+            let name = match cli.name.get(idx) {
+                Some(name) => name.clone(),
+                None => format!("case_{}", idx + 1),
+            };
+            consensus::case_literal(&perfts, &name, fen, cli.depth)
+        };
+        match result {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
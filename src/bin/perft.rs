@@ -0,0 +1,106 @@
+use clap::Parser;
+
+use std::process;
+
+use chess_bench::{impls, parallel::Parallel, tt::PerftTable, Error, Perft};
+
+#[derive(Parser)]
+#[clap(
+    name = "perft",
+    version,
+    about = "Runs perft or hperft for the given chess implementation"
+)]
+struct Cli {
+    #[clap(value_parser)]
+    #[clap(help = "Chess implementation name")]
+    name: String,
+
+    #[clap(value_parser)]
+    #[clap(help = "Position to start from")]
+    fen: String,
+
+    #[clap(value_parser)]
+    #[clap(help = "Perft depth")]
+    depth: usize,
+
+    #[clap(short = 'H', long, action)]
+    #[clap(help = "Run hperft instead of perft")]
+    hperft: bool,
+
+    #[clap(long, action)]
+    #[clap(help = "Run hperft v2, which also hashes piece types and game state")]
+    v2: bool,
+
+    #[clap(short, long, action)]
+    #[clap(help = "Show perft results for each legal move")]
+    divide: bool,
+
+    #[clap(short, long, action)]
+    #[clap(help = "Show detailed statistics for the leaf nodes")]
+    stats: bool,
+
+    #[clap(short = 'j', long, default_value_t = 1)]
+    #[clap(help = "Number of threads")]
+    threads: usize,
+
+    #[clap(long, action)]
+    #[clap(help = "Check incrementally updated hashes against the ones rebuilt from FEN")]
+    verify_hash: bool,
+
+    #[clap(long, value_name = "MB")]
+    #[clap(conflicts_with_all = ["hperft", "v2", "divide", "stats", "verify_hash"])]
+    #[clap(help = "Use transposition table of the given size (single-threaded perft only)")]
+    hash: Option<usize>,
+}
+
+fn run(cli: &Cli, p: &dyn Perft) -> Result<(), Error> {
+    if cli.divide {
+        let mut total = 0;
+        for (mv, count) in p.divide(&cli.fen, cli.depth)? {
+            println!("{}: {}", mv, count);
+            total += count;
+        }
+        println!("total: {}", total);
+    } else if cli.verify_hash {
+        match p.verify_hash(&cli.fen, cli.depth)? {
+            Some(mismatch) => {
+                println!("{}", mismatch);
+                process::exit(1);
+            }
+            None => println!("no mismatch found"),
+        }
+    } else if cli.stats {
+        println!("{:#?}", p.perft_stats(&cli.fen, cli.depth)?);
+    } else if let Some(mb) = cli.hash {
+        let mut table = PerftTable::new(mb << 20);
+        println!("{}", p.hashed_perft(&cli.fen, cli.depth, &mut table)?);
+    } else if cli.v2 {
+        println!("{}", p.hperft_v2(&cli.fen, cli.depth)?);
+    } else if cli.hperft {
+        println!("{}", p.hperft(&cli.fen, cli.depth)?);
+    } else {
+        println!("{}", p.perft(&cli.fen, cli.depth)?);
+    }
+    Ok(())
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.threads == 0 {
+        eprintln!("error: need at least one thread");
+        process::exit(1);
+    }
+    if cli.hash.is_some() && cli.threads != 1 {
+        eprintln!("error: --hash can only be used single-threaded");
+        process::exit(1);
+    }
+    let inner = impls::find_perft(&cli.name).unwrap_or_else(|| {
+        eprintln!("error: unknown implementation {}", cli.name);
+        process::exit(1);
+    });
+    let p = Parallel::new(inner.as_ref(), cli.threads);
+    if let Err(e) = run(&cli, &p) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
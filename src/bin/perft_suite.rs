@@ -0,0 +1,66 @@
+use clap::Parser;
+
+use std::process;
+
+use chess_bench::{impls, suite};
+
+#[derive(Parser)]
+#[clap(
+    name = "perft_suite",
+    version,
+    about = "Checks chess implementations against perft suite in EPD format"
+)]
+struct Cli {
+    #[clap(value_parser)]
+    #[clap(help = "Perft suite file")]
+    file: String,
+
+    #[clap(short, long)]
+    #[clap(help = "Skip the entries with greater depth")]
+    depth: Option<usize>,
+
+    #[clap(short, long = "impl")]
+    #[clap(help = "Check only the given implementation")]
+    implementation: Option<String>,
+
+    #[clap(long, action)]
+    #[clap(help = "Run in Chess960 mode, skipping the implementations which don't support it")]
+    chess960: bool,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let cases = suite::load_epd(&cli.file).unwrap_or_else(|e| {
+        eprintln!("error: {}: {}", cli.file, e);
+        process::exit(1);
+    });
+    let mut perfts = match &cli.implementation {
+        Some(name) => vec![impls::find_perft(name).unwrap_or_else(|| {
+            eprintln!("error: unknown implementation {}", name);
+            process::exit(1);
+        })],
+        None => impls::all_perft(),
+    };
+    if cli.chess960 {
+        perfts.retain(|p| p.supports_chess960());
+        if perfts.is_empty() {
+            eprintln!("error: no implementations supporting chess960");
+            process::exit(1);
+        }
+    }
+    let mut failed = false;
+    for p in &perfts {
+        let failures = match cli.chess960 {
+            true => suite::run_suite_chess960(p.as_ref(), &cases, cli.depth),
+            false => suite::run_suite(p.as_ref(), &cases, cli.depth),
+        };
+        println!("{}: {} failures", p.name(), failures.len());
+        for failure in &failures {
+            println!("{}", failure);
+        }
+        failed |= !failures.is_empty();
+    }
+    if failed {
+        process::exit(1);
+    }
+}
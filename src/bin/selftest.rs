@@ -1,35 +1,103 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use std::io::{self, BufRead};
+use std::process;
 
 use chess_bench::{
-    impls,
-    selftest::{Options, Tester},
-    Test,
+    diff, golden,
+    impls::{self, Implementation},
+    selftest::{self, DepthSpec, DynTester, Options, OutputFormat},
 };
 
 #[derive(Parser)]
 #[clap(
     name = "selftest",
     version,
-    about = "Runs various chess implementations on a set of positions from stdin"
+    about = "Runs various chess implementations on a set of positions from stdin",
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    #[clap(value_parser)]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(value_parser, required_unless_present = "list")]
     #[clap(help = "Chess implementation name")]
-    name: String,
+    name: Option<String>,
+
+    #[clap(long, action)]
+    #[clap(help = "List the available implementations and their capabilities")]
+    list: bool,
 
-    #[clap(short, long, action)]
+    #[clap(short, long, action, global = true)]
     #[clap(help = "Dump trace chains (bigger and more time-consuming)")]
     large_chains: bool,
 
-    #[clap(short = 'A', long, action)]
+    #[clap(short = 'A', long, action, global = true)]
     #[clap(help = "Disable attack heatmaps")]
     no_attack_heatmaps: bool,
 
-    #[clap(short = 'D', long, action)]
+    #[clap(short = 'D', long, action, global = true)]
     #[clap(help = "Run on reduced depth")]
     reduced_depth: bool,
+
+    #[clap(long, value_name = "SPECS", value_delimiter = ',', global = true)]
+    #[clap(
+        help = "Run the given depth dumps instead of the default ones, e.g. \"1,2,4-heatmaps\""
+    )]
+    depth_specs: Option<Vec<DepthSpec>>,
+
+    #[clap(short = 'F', long, action, global = true)]
+    #[clap(help = "Check FEN export round trip in each node (slower)")]
+    fen_round_trip: bool,
+
+    #[clap(short = 'T', long, action, global = true)]
+    #[clap(help = "Check terminal status (checkmate, stalemate, insufficient material)")]
+    terminal_status: bool,
+
+    #[clap(short = 'S', long, action, global = true)]
+    #[clap(help = "List moves in SAN and check SAN round trip")]
+    san: bool,
+
+    #[clap(long, action)]
+    #[clap(help = "Write the results as JSON lines, one object per position")]
+    json: bool,
+
+    #[clap(short = 'U', long, action)]
+    #[clap(help = "Only check that UCI move parser accepts exactly the legal moves")]
+    uci_moves: bool,
+
+    #[clap(short = 'j', long, default_value_t = 1)]
+    #[clap(help = "Number of worker threads, the output is still written in input order")]
+    jobs: usize,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(
+        about = "Run two implementations in lockstep and show the first position where they differ"
+    )]
+    Diff {
+        #[clap(value_parser)]
+        #[clap(help = "First chess implementation name")]
+        first: String,
+
+        #[clap(value_parser)]
+        #[clap(help = "Second chess implementation name")]
+        second: String,
+
+        #[clap(short, long, action)]
+        #[clap(
+            help = "On depth dump mismatch, find the shortest move chain where the positions differ"
+        )]
+        minimize_chain: bool,
+    },
+
+    #[clap(about = "Print the digests of the output for each position, to be used as golden file")]
+    Golden {
+        #[clap(value_parser)]
+        #[clap(help = "Reference chess implementation name")]
+        reference: String,
+    },
 }
 
 impl Cli {
@@ -38,27 +106,166 @@ impl Cli {
             dump_trace_chains: self.large_chains,
             attack_heatmaps: !self.no_attack_heatmaps,
             big_depth: !self.reduced_depth,
+            fen_round_trip: self.fen_round_trip,
+            terminal_status: self.terminal_status,
+            san: self.san,
+            format: match self.json {
+                true => OutputFormat::Json,
+                false => OutputFormat::Text,
+            },
+            custom_depth_specs: self.depth_specs.clone().map(Into::into),
             ..Default::default()
         }
     }
 }
 
-fn run_with<R: BufRead, T: Test>(r: &mut R, t: T, opts: Options) {
+fn run_with(r: &mut dyn BufRead, t: &dyn DynTester, cli: &Cli) {
     let mut stdout = io::stdout().lock();
-    let mut tester = Tester::new(t, opts, &mut stdout);
-    tester.run_many(r);
+    if cli.uci_moves {
+        let count = t.check_uci_moves_many(&cli.options(), r, &mut stdout);
+        if count != 0 {
+            eprintln!("{} mismatches found", count);
+            process::exit(1);
+        }
+        return;
+    }
+    if cli.jobs == 1 {
+        t.run_many(&cli.options(), r, &mut stdout);
+    } else {
+        selftest::run_many_parallel(t, &cli.options(), r, &mut stdout, cli.jobs);
+    }
 }
 
-fn run<R: BufRead>(name: &str, r: &mut R, opts: Options) {
-    match name {
-        "chess" => run_with(r, impls::chess::Test, opts),
-        "owlchess" => run_with(r, impls::owlchess::Test, opts),
-        _ => panic!("unknown implementation {}", name),
+// Finds the implementation, which must support selftest and the checks requested in `cli`.
+fn find(cli: &Cli, name: &str) -> Implementation {
+    let imp = impls::find(name).unwrap_or_else(|| {
+        eprintln!("error: unknown implementation {}", name);
+        process::exit(1);
+    });
+    if imp.selftest.is_none() {
+        eprintln!("error: {} doesn't support selftest", name);
+        process::exit(1);
+    }
+    let checks = [
+        (cli.fen_round_trip, imp.caps.fen_export, "FEN export"),
+        (cli.san, imp.caps.san, "SAN"),
+        (cli.uci_moves, imp.caps.uci_moves, "UCI move parsing"),
+    ];
+    if let Some((_, _, feature)) = checks
+        .iter()
+        .find(|(used, supported, _)| *used && !supported)
+    {
+        eprintln!("error: {} doesn't support {}", name, feature);
+        process::exit(1);
     }
+    imp
+}
+
+fn run(cli: &Cli, name: &str, r: &mut dyn BufRead) {
+    let imp = find(cli, name);
+    run_with(r, imp.selftest.unwrap().as_ref(), cli);
+}
+
+fn run_diff(cli: &Cli, first: &str, second: &str, minimize_chain: bool, r: &mut dyn BufRead) {
+    let (first, second) = (find(cli, first), find(cli, second));
+    let Some(mut difference) = diff::diff_many(&first, &second, &cli.options(), r) else {
+        println!("no differences found");
+        return;
+    };
+    // Depth dump hashes only tell that something differs in the subtree, so we search for the
+    // exact position.
+    if minimize_chain && difference.field.starts_with("depth-dump-at-") {
+        let options = cli.options();
+        let depth = options
+            .depth_specs()
+            .iter()
+            .map(|s| s.depth)
+            .max()
+            .unwrap_or(0);
+        match diff::minimize_chain(&first, &second, &options, &difference.fen, depth) {
+            Ok(Some(chain_difference)) => difference = chain_difference,
+            Ok(None) => eprintln!("warning: no differing position found in the subtree"),
+            Err(e) => eprintln!("warning: cannot minimize the chain: {}", e),
+        }
+    }
+    println!("{}", difference);
+    process::exit(1);
+}
+
+fn run_golden(cli: &Cli, reference: &str, r: &mut dyn BufRead) {
+    let imp = find(cli, reference);
+    let digests = golden::digests(imp.selftest.unwrap().as_ref(), &cli.options(), r);
+    golden::write_golden(&mut io::stdout().lock(), imp.name, &digests).unwrap();
+}
+
+fn features(imp: &Implementation) -> Vec<&'static str> {
+    let flags = [
+        (imp.selftest.is_some(), "selftest"),
+        (imp.caps.fen_export, "fen-export"),
+        (imp.caps.terminal_status, "terminal-status"),
+        (imp.caps.uci_moves, "uci-moves"),
+        (imp.caps.san, "san"),
+        (imp.perft.supports_chess960(), "chess960"),
+    ];
+    flags
+        .into_iter()
+        .filter(|&(supported, _)| supported)
+        .map(|(_, name)| name)
+        .collect()
 }
 
 fn main() {
     let cli = Cli::parse();
+    if cli.jobs == 0 {
+        eprintln!("error: need at least one job");
+        process::exit(1);
+    }
+    let specs = cli.depth_specs.as_deref().unwrap_or_default();
+    if cli.no_attack_heatmaps && specs.iter().any(|s| s.with_heatmaps) {
+        eprintln!("error: depth dumps with heatmaps cannot be used with --no-attack-heatmaps");
+        process::exit(1);
+    }
+    if cli.large_chains && cli.json {
+        eprintln!("error: --large-chains cannot be used with --json");
+        process::exit(1);
+    }
+    if cli.uci_moves && cli.jobs != 1 {
+        eprintln!("error: --uci-moves can only be used single-threaded");
+        process::exit(1);
+    }
+    if cli.list {
+        for imp in impls::registry() {
+            println!("{}: {}", imp.name, features(&imp).join(" "));
+        }
+        return;
+    }
     let mut stdin = io::stdin().lock();
-    run(&cli.name, &mut stdin, cli.options());
+    match &cli.command {
+        Some(Command::Diff {
+            first,
+            second,
+            minimize_chain,
+        }) => {
+            run_diff(&cli, first, second, *minimize_chain, &mut stdin);
+            return;
+        }
+        Some(Command::Golden { reference }) => {
+            run_golden(&cli, reference, &mut stdin);
+            return;
+        }
+        None => {}
+    }
+    let name = cli.name.as_deref().unwrap();
+    run(&cli, name, &mut stdin);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
 }
@@ -0,0 +1,153 @@
+use crate::{Error, Perft};
+use std::fmt;
+use std::ops::ControlFlow;
+
+pub struct Mismatch {
+    pub first: &'static str,
+    pub second: &'static str,
+    pub path: Vec<String>,
+    pub fen: String,
+    pub only_first: Vec<String>,
+    pub only_second: Vec<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mismatch between {} and {}", self.first, self.second)?;
+        writeln!(f, "path: [{}]", self.path.join(" "))?;
+        writeln!(f, "fen: {}", self.fen)?;
+        writeln!(f, "only in {}: [{}]", self.first, self.only_first.join(" "))?;
+        write!(
+            f,
+            "only in {}: [{}]",
+            self.second,
+            self.only_second.join(" ")
+        )
+    }
+}
+
+// Node of the game tree, reached from the root by `chain`. Each implementation makes the moves on
+// its own, so the positions are kept separately for both of them.
+pub(crate) struct Node {
+    pub chain: Vec<String>,
+    pub fens: [String; 2],
+}
+
+// Walks the game tree from `fen` level by level, at most `depth` plies deep, and returns the first
+// result found by `visit`. As the levels are visited in order, the move chain to the node with the
+// result is the shortest possible. Besides the node, `visit` gets the remaining depth, and returns
+// either the result or the moves to continue with.
+pub(crate) fn search_levels<T>(
+    perfts: [&dyn Perft; 2],
+    fen: &str,
+    depth: usize,
+    mut visit: impl FnMut(&Node, usize) -> Result<ControlFlow<T, Vec<String>>, Error>,
+) -> Result<Option<T>, Error> {
+    let mut level = vec![Node {
+        chain: Vec::new(),
+        fens: [fen.to_string(), fen.to_string()],
+    }];
+    for ply in 0..=depth {
+        let mut next = Vec::new();
+        for node in &level {
+            let moves = match visit(node, depth - ply)? {
+                ControlFlow::Break(result) => return Ok(Some(result)),
+                ControlFlow::Continue(moves) => moves,
+            };
+            if ply == depth {
+                continue;
+            }
+            for mv in moves {
+                let mut chain = node.chain.clone();
+                chain.push(mv.clone());
+                let fens = [
+                    perfts[0].make_move(&node.fens[0], &mv)?,
+                    perfts[1].make_move(&node.fens[1], &mv)?,
+                ];
+                next.push(Node { chain, fens });
+            }
+        }
+        level = next;
+    }
+    Ok(None)
+}
+
+// Finds the shortest move path to the position where `first` and `second` disagree on the set of
+// legal moves, following only the moves with different perft results. Returns `None` if the
+// implementations agree on the given depth.
+//
+// `fen` in the result is obtained via `first.make_move()`.
+pub fn bisect(
+    first: &dyn Perft,
+    second: &dyn Perft,
+    fen: &str,
+    depth: usize,
+) -> Result<Option<Mismatch>, Error> {
+    search_levels([first, second], fen, depth, |node, depth| {
+        if depth == 0 {
+            return Ok(ControlFlow::Continue(Vec::new()));
+        }
+        let div_first = first.divide(&node.fens[0], depth)?;
+        let div_second = second.divide(&node.fens[1], depth)?;
+
+        let only_first: Vec<_> = div_first
+            .keys()
+            .filter(|mv| !div_second.contains_key(*mv))
+            .cloned()
+            .collect();
+        let only_second: Vec<_> = div_second
+            .keys()
+            .filter(|mv| !div_first.contains_key(*mv))
+            .cloned()
+            .collect();
+        if !only_first.is_empty() || !only_second.is_empty() {
+            return Ok(ControlFlow::Break(Mismatch {
+                first: first.name(),
+                second: second.name(),
+                path: node.chain.clone(),
+                fen: node.fens[0].clone(),
+                only_first,
+                only_second,
+            }));
+        }
+
+        Ok(ControlFlow::Continue(
+            div_first
+                .into_iter()
+                .filter(|(mv, count)| div_second[mv] != *count)
+                .map(|(mv, _)| mv)
+                .collect(),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{impls, testutil::Broken};
+
+    #[test]
+    fn test_bisect() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let perfts = impls::all_perft();
+        for p in &perfts[1..] {
+            let mismatch = bisect(perfts[0].as_ref(), p.as_ref(), fen, 3).unwrap();
+            assert!(mismatch.is_none());
+        }
+
+        let p = &impls::owlchess::Perft;
+        let broken_fen = p
+            .make_move(&p.make_move(fen, "e1g1").unwrap(), "h3g2")
+            .unwrap();
+        let broken = Broken {
+            inner: p,
+            fen: &broken_fen,
+            mv: "g1g2",
+        };
+        let mismatch = bisect(p, &broken, fen, 3).unwrap().unwrap();
+        assert_eq!(mismatch.path, ["e1g1", "h3g2"]);
+        assert_eq!(mismatch.fen, broken_fen);
+        assert_eq!(mismatch.only_first, ["g1g2"]);
+        assert!(mismatch.only_second.is_empty());
+    }
+}
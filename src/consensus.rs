@@ -0,0 +1,145 @@
+use crate::{Error, Perft};
+use std::fmt::{self, Debug, Write};
+
+// Results which differ between the implementations. Each group contains the result and the
+// implementations which returned it.
+#[derive(Debug)]
+pub struct Disagreement {
+    pub fen: String,
+    pub what: String,
+    pub groups: Vec<(String, Vec<&'static str>)>,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "implementations disagree on {} for \"{}\"",
+            self.what, self.fen
+        )?;
+        for (result, names) in &self.groups {
+            write!(f, "\n  {}: {}", names.join(", "), result)?;
+        }
+        Ok(())
+    }
+}
+
+fn agree<T: PartialEq + Debug>(
+    perfts: &[&dyn Perft],
+    fen: &str,
+    what: String,
+    func: impl Fn(&dyn Perft) -> Result<T, Error>,
+) -> Result<T, Disagreement> {
+    let mut groups: Vec<(Result<T, Error>, Vec<&'static str>)> = Vec::new();
+    for p in perfts {
+        let result = func(*p);
+        match groups.iter_mut().find(|(r, _)| *r == result) {
+            Some((_, names)) => names.push(p.name()),
+            None => groups.push((result, vec![p.name()])),
+        }
+    }
+    match groups.len() {
+        1 if groups[0].0.is_ok() => Ok(groups.pop().unwrap().0.unwrap()),
+        _ => Err(Disagreement {
+            fen: fen.to_string(),
+            what,
+            groups: groups
+                .into_iter()
+                .map(|(result, names)| {
+                    let result = match result {
+                        Ok(value) => format!("{:?}", value),
+                        Err(e) => format!("error: {}", e),
+                    };
+                    (result, names)
+                })
+                .collect(),
+        }),
+    }
+}
+
+// Returns `Case` literal to be pasted into `perft::CASES`, if all the implementations agree.
+pub fn case_literal(
+    perfts: &[&dyn Perft],
+    name: &str,
+    fen: &str,
+    depth: usize,
+) -> Result<String, Disagreement> {
+    let perft = agree(perfts, fen, format!("perft {}", depth), |p| {
+        p.perft(fen, depth)
+    })?;
+    let hperft = agree(perfts, fen, format!("hperft {}", depth), |p| {
+        p.hperft(fen, depth)
+    })?;
+    let hperft_v2 = agree(perfts, fen, format!("hperft v2 {}", depth), |p| {
+        p.hperft_v2(fen, depth)
+    })?;
+    let stats = agree(perfts, fen, format!("perft stats {}", depth), |p| {
+        p.perft_stats(fen, depth)
+    })?;
+
+    let mut s = String::new();
+    writeln!(s, "    Case {{").unwrap();
+    writeln!(s, "        name: {:?},", name).unwrap();
+    writeln!(s, "        fen: {:?},", fen).unwrap();
+    writeln!(s, "        depth: {},", depth).unwrap();
+    writeln!(s, "        perft: {},", perft).unwrap();
+    writeln!(s, "        hperft: {},", hperft).unwrap();
+    writeln!(s, "        hperft_v2: {},", hperft_v2).unwrap();
+    let stats = format!("{:#?}", stats).replace('\n', "\n        ");
+    writeln!(s, "        stats: {},", stats).unwrap();
+    write!(s, "    }},").unwrap();
+    Ok(s)
+}
+
+// Returns perft suite line in EPD format with results for all the depths from 1 to `depth`, if
+// all the implementations agree.
+pub fn epd_line(perfts: &[&dyn Perft], fen: &str, depth: usize) -> Result<String, Disagreement> {
+    let mut s = fen.to_string();
+    for d in 1..=depth {
+        let perft = agree(perfts, fen, format!("perft {}", d), |p| p.perft(fen, d))?;
+        write!(s, " ;D{} {}", d, perft).unwrap();
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{impls, perft::CASES, suite, testutil::Broken};
+
+    #[test]
+    fn test_case_literal() {
+        let perfts = impls::all_perft();
+        let perfts: Vec<_> = perfts.iter().map(|p| p.as_ref()).collect();
+        let case = CASES.iter().find(|c| c.name == "initial").unwrap();
+        let literal = case_literal(&perfts, case.name, case.fen, 3).unwrap();
+        assert!(literal.contains("        perft: 8902,\n"));
+        assert!(literal.contains("            checkmates: 0,\n"));
+        assert!(literal.ends_with("        },\n    },"));
+
+        let line = epd_line(&perfts, case.fen, 3).unwrap();
+        let parsed = suite::parse_epd(&line).unwrap();
+        assert_eq!(parsed[0].fen, case.fen);
+        assert_eq!(parsed[0].expected, [(1, 20), (2, 400), (3, 8902)]);
+    }
+
+    #[test]
+    fn test_disagreement() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let p = &impls::chess::Perft;
+        let broken = Broken {
+            inner: p,
+            fen,
+            mv: "e2e4",
+        };
+        let err = epd_line(&[p, &broken, &impls::pleco::Perft], fen, 2).unwrap_err();
+        assert_eq!(err.what, "perft 1");
+        assert_eq!(
+            err.groups,
+            [
+                ("20".to_string(), vec!["chess", "pleco"]),
+                ("19".to_string(), vec!["broken"])
+            ]
+        );
+    }
+}
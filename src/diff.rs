@@ -0,0 +1,384 @@
+use crate::bisect::search_levels;
+use crate::impls::Implementation;
+use crate::selftest::{DynTester, Options, Report};
+use crate::Error;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+use std::ops::ControlFlow;
+
+// First field of the selftest report on which two implementations disagree. `lines` contain the
+// side-by-side rendering of this field, where the missing lines are replaced with "-".
+pub struct Difference {
+    pub first: &'static str,
+    pub second: &'static str,
+    pub fen: String,
+    // Moves leading from `fen` to the position where the implementations disagree, and its FEN (as
+    // exported by the first implementation). Empty if they already disagree on `fen`.
+    pub chain: Vec<String>,
+    pub chain_fen: String,
+    pub field: String,
+    pub lines: Vec<(String, String)>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "difference between {} and {}", self.first, self.second)?;
+        writeln!(f, "fen: {}", self.fen)?;
+        if !self.chain.is_empty() {
+            writeln!(f, "chain: {}", self.chain.join(" "))?;
+            writeln!(f, "chain-fen: {}", self.chain_fen)?;
+        }
+        writeln!(f, "field: {}", self.field)?;
+        let width = self
+            .lines
+            .iter()
+            .map(|(l, _)| l.len())
+            .chain([self.first.len()])
+            .max()
+            .unwrap();
+        write!(f, "  {:width$} | {}", self.first, self.second)?;
+        // Long runs of equal lines are collapsed, only `CONTEXT` lines around each differing line
+        // are shown.
+        let is_shown = |i: usize| {
+            let lo = i.saturating_sub(CONTEXT);
+            let hi = (i + CONTEXT + 1).min(self.lines.len());
+            self.lines[lo..hi].iter().any(|(l, r)| l != r)
+        };
+        let mut skipped = 0;
+        for (i, (l, r)) in self.lines.iter().enumerate() {
+            if !is_shown(i) {
+                skipped += 1;
+                continue;
+            }
+            if skipped != 0 {
+                write!(f, "\n  ... ({} equal lines)", skipped)?;
+                skipped = 0;
+            }
+            let mark = if l != r { '!' } else { ' ' };
+            write!(f, "\n{} {:width$} | {}", mark, l, r)?;
+        }
+        if skipped != 0 {
+            write!(f, "\n  ... ({} equal lines)", skipped)?;
+        }
+        Ok(())
+    }
+}
+
+const CONTEXT: usize = 2;
+
+fn bool_str(val: bool) -> String {
+    val.to_string()
+}
+
+fn single(l: String, r: String) -> Option<Vec<(String, String)>> {
+    (l != r).then(|| vec![(l, r)])
+}
+
+// Aligns two lists of `(key, line)` pairs by key, so the lines present only on one side are put
+// against "-".
+fn align(l: Vec<(String, String)>, r: Vec<(String, String)>) -> Option<Vec<(String, String)>> {
+    let mut lines: BTreeMap<String, (String, String)> = BTreeMap::new();
+    for (key, line) in l {
+        lines.entry(key).or_insert(("-".into(), "-".into())).0 = line;
+    }
+    for (key, line) in r {
+        lines.entry(key).or_insert(("-".into(), "-".into())).1 = line;
+    }
+    let lines: Vec<_> = lines.into_values().collect();
+    lines.iter().any(|(l, r)| l != r).then_some(lines)
+}
+
+fn heatmap_lines(heatmap: u64) -> Vec<String> {
+    (0..8)
+        .rev()
+        .map(|y| {
+            (0..8)
+                .map(|x| match (heatmap >> (y * 8 + x)) & 1 != 0 {
+                    true => '#',
+                    false => '.',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Compares the reports field by field, in the order of the text output. Returns the name of the
+// first field which differs, along with its side-by-side rendering.
+pub fn compare_reports(l: &Report, r: &Report) -> Option<(String, Vec<(String, String)>)> {
+    let to_lines = |val: &Option<String>| val.clone().unwrap_or_else(|| "-".to_string());
+    if let Some(lines) = single(to_lines(&l.exported_fen), to_lines(&r.exported_fen)) {
+        return Some(("exported-fen".to_string(), lines));
+    }
+
+    let moves = |report: &Report| -> Vec<_> {
+        report
+            .moves
+            .iter()
+            .map(|mv| (mv.clone(), mv.clone()))
+            .collect()
+    };
+    if let Some(lines) = align(moves(l), moves(r)) {
+        return Some(("moves".to_string(), lines));
+    }
+
+    let sans = |report: &Report| -> Vec<_> {
+        let sans = report.san.iter().flatten();
+        sans.map(|(uci, san, error)| {
+            let line = match error {
+                Some(error) => format!("{} {} ({})", uci, san, error),
+                None => format!("{} {}", uci, san),
+            };
+            (uci.clone(), line)
+        })
+        .collect()
+    };
+    if let Some(lines) = align(sans(l), sans(r)) {
+        return Some(("san".to_string(), lines));
+    }
+
+    if let Some(lines) = single(bool_str(l.is_check), bool_str(r.is_check)) {
+        return Some(("check?".to_string(), lines));
+    }
+
+    if let (Some(l_status), Some(r_status)) = (l.terminal_status, r.terminal_status) {
+        let names = ["checkmate?", "stalemate?", "insufficient-material?"];
+        for ((name, l), r) in names.iter().zip(l_status).zip(r_status) {
+            // Statuses unsupported by one of the implementations are not compared.
+            let (Some(l), Some(r)) = (l, r) else {
+                continue;
+            };
+            if let Some(lines) = single(bool_str(l), bool_str(r)) {
+                return Some((name.to_string(), lines));
+            }
+        }
+    }
+
+    if let (Some(l_heatmaps), Some(r_heatmaps)) = (l.heatmaps, r.heatmaps) {
+        let colors = ["white", "black"];
+        for ((color, l), r) in colors.iter().zip(l_heatmaps).zip(r_heatmaps) {
+            let (l, r) = (heatmap_lines(l), heatmap_lines(r));
+            if let Some(rank) = l.iter().zip(&r).position(|(l, r)| l != r) {
+                let field = format!("{}-heatmap, rank {}", color, 8 - rank);
+                return Some((field, l.into_iter().zip(r).collect()));
+            }
+        }
+    }
+
+    for (l, r) in l.depth_dumps.iter().zip(&r.depth_dumps) {
+        if let Some(lines) = single(l.hash.to_string(), r.hash.to_string()) {
+            return Some((format!("depth-dump-at-{}", l.spec.name()), lines));
+        }
+    }
+
+    None
+}
+
+type Lines = Vec<(String, String)>;
+
+// Same as `compare_reports()`, but also handles the positions which cannot be parsed. If both
+// implementations fail, the position is considered equal.
+fn compare_results(
+    l: &Result<Report, Error>,
+    r: &Result<Report, Error>,
+) -> Option<(String, Lines)> {
+    match (l, r) {
+        (Ok(l), Ok(r)) => compare_reports(l, r),
+        (Err(_), Err(_)) => None,
+        (l, r) => {
+            let to_line = |res: &Result<Report, Error>| match res {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            };
+            Some(("error".to_string(), vec![(to_line(l), to_line(r))]))
+        }
+    }
+}
+
+fn selftest(imp: &Implementation) -> &dyn DynTester {
+    imp.selftest
+        .as_deref()
+        .unwrap_or_else(|| panic!("{} doesn't support selftest", imp.name))
+}
+
+// Runs the selftest for both implementations on each position from `reader` in lockstep, and stops
+// on the first position where the reports differ. Positions which cannot be parsed by both
+// implementations are skipped.
+pub fn diff_many(
+    first: &Implementation,
+    second: &Implementation,
+    options: &Options,
+    reader: &mut dyn BufRead,
+) -> Option<Difference> {
+    for line in reader.lines() {
+        let line = line.expect("i/o error");
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let l = selftest(first).report(options, line);
+        let r = selftest(second).report(options, line);
+        if let Some((field, lines)) = compare_results(&l, &r) {
+            return Some(Difference {
+                first: first.name,
+                second: second.name,
+                fen: line.to_string(),
+                chain: Vec::new(),
+                chain_fen: line.to_string(),
+                field,
+                lines,
+            });
+        }
+    }
+    None
+}
+
+// Finds the shortest move chain from `fen` which leads to a position where the implementations
+// disagree on the move set, heatmaps or check flag (or other fields enabled in `options`), looking
+// at most `depth` plies deep. Useful to explain the depth dump mismatches.
+pub fn minimize_chain(
+    first: &Implementation,
+    second: &Implementation,
+    options: &Options,
+    fen: &str,
+    depth: usize,
+) -> Result<Option<Difference>, Error> {
+    let perfts = [first.perft.as_ref(), second.perft.as_ref()];
+    search_levels(perfts, fen, depth, |node, _| {
+        let l = selftest(first).report_node(options, &node.fens[0]);
+        let r = selftest(second).report_node(options, &node.fens[1]);
+        if let Some((field, lines)) = compare_results(&l, &r) {
+            return Ok(ControlFlow::Break(Difference {
+                first: first.name,
+                second: second.name,
+                fen: fen.to_string(),
+                chain: node.chain.clone(),
+                chain_fen: node.fens[0].clone(),
+                field,
+                lines,
+            }));
+        }
+        Ok(ControlFlow::Continue(
+            l.map_or(Vec::new(), |report| report.moves),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{impls, testutil::BrokenTest};
+
+    const FENS: &str = "\
+r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4
+4k3/8/8/3pPp2/8/8/8/4K3 w - d6 0 1
+";
+
+    fn options() -> Options {
+        Options {
+            big_depth: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_many() {
+        let first = impls::find("mailbox").unwrap();
+        let second = impls::find("owlchess").unwrap();
+        assert!(diff_many(&first, &second, &options(), &mut FENS.as_bytes()).is_none());
+
+        let diff = diff_many(&first, &second, &options(), &mut "invalid\n".as_bytes());
+        assert!(diff.is_none());
+    }
+
+    #[test]
+    fn test_minimize_chain() {
+        let fen = FENS.lines().next().unwrap();
+        let reference = impls::find("mailbox").unwrap();
+        let chain = ["e1g1", "d7d6", "b1c3"];
+        let broken_fen = "r1bqk1nr/ppp2ppp/2np4/2b1p3/2B1P3/2N2N2/PPPP1PPP/R1BQ1RK1 b kq - 1 5";
+        let chain_fen = chain.iter().fold(fen.to_string(), |fen, mv| {
+            reference.perft.make_move(&fen, mv).unwrap()
+        });
+        assert_eq!(chain_fen, broken_fen);
+        let broken = Implementation {
+            name: "broken",
+            perft: Box::new(impls::mailbox::Perft),
+            selftest: Some(Box::new(BrokenTest {
+                fen: broken_fen,
+                mv: "c8g4",
+            })),
+            caps: Default::default(),
+            reference_only: false,
+        };
+
+        let diff = minimize_chain(&reference, &broken, &options(), fen, 2).unwrap();
+        assert!(diff.is_none());
+        let diff = minimize_chain(&reference, &broken, &options(), fen, 3)
+            .unwrap()
+            .unwrap();
+        // The moves may come in other order, as the first chain found is "b1c3 d7d6 e1g1".
+        assert_eq!(diff.chain.len(), chain.len());
+        assert_eq!(diff.chain_fen, broken_fen);
+        assert_eq!(diff.field, "moves");
+        assert_eq!(diff.lines.iter().filter(|(l, r)| l != r).count(), 1);
+        assert!(diff.lines.contains(&("c8g4".to_string(), "-".to_string())));
+    }
+
+    #[test]
+    fn test_compare_reports() {
+        let fen = FENS.lines().next().unwrap();
+        let report = impls::mailbox::Test.report(&options(), fen).unwrap();
+        assert!(compare_reports(&report, &report).is_none());
+
+        let mut other = report.clone();
+        other.moves.retain(|mv| mv != "e1g1");
+        let (field, lines) = compare_reports(&report, &other).unwrap();
+        assert_eq!(field, "moves");
+        assert!(lines.contains(&("e1g1".to_string(), "-".to_string())));
+        assert!(lines.contains(&("a2a3".to_string(), "a2a3".to_string())));
+
+        let mut other = report.clone();
+        other.heatmaps.as_mut().unwrap()[1] ^= 1 << 20;
+        let (field, lines) = compare_reports(&report, &other).unwrap();
+        assert_eq!(field, "black-heatmap, rank 3");
+        assert_eq!(lines.len(), 8);
+
+        let mut other = report.clone();
+        other.depth_dumps[1].hash += 1;
+        let (field, _) = compare_reports(&report, &other).unwrap();
+        assert_eq!(field, "depth-dump-at-2");
+    }
+
+    #[test]
+    fn test_display() {
+        let line = |l: &str, r: &str| (l.to_string(), r.to_string());
+        let mut lines: Vec<_> = (0..10)
+            .map(|i| line(&i.to_string(), &i.to_string()))
+            .collect();
+        lines[7] = line("a7a8q", "-");
+        let diff = Difference {
+            first: "first",
+            second: "second",
+            fen: "fen".to_string(),
+            chain: vec!["e2e4".to_string(), "e7e5".to_string()],
+            chain_fen: "chain fen".to_string(),
+            field: "moves".to_string(),
+            lines,
+        };
+        let expected = "\
+difference between first and second
+fen: fen
+chain: e2e4 e7e5
+chain-fen: chain fen
+field: moves
+  first | second
+  ... (5 equal lines)
+  5     | 5
+  6     | 6
+! a7a8q | -
+  8     | 8
+  9     | 9";
+        assert_eq!(diff.to_string(), expected);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::selftest::{DynTester, Options};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+// Only the beginning of SHA-256 is kept, which is enough to tell the outputs apart, and keeps the
+// golden file small.
+const DIGEST_BYTES: usize = 8;
+
+// Runs the selftest on each position from `reader` separately, and calls `visit` with the position
+// and its output. Concatenating the outputs gives exactly the output of `DynTester::run_many()`.
+pub fn for_each_position(
+    test: &dyn DynTester,
+    options: &Options,
+    reader: &mut dyn BufRead,
+    mut visit: impl FnMut(&str, &[u8]),
+) {
+    let mut output = Vec::new();
+    for line in reader.lines() {
+        let line = line.expect("i/o error");
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        output.clear();
+        test.run_many(options, &mut line.as_bytes(), &mut output);
+        visit(line, &output);
+    }
+}
+
+pub fn digest(output: &[u8]) -> String {
+    let hash = Sha256::digest(output);
+    hash[..DIGEST_BYTES]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Returns the digests of the selftest output for each position from `reader`, along with the
+// positions themselves.
+pub fn digests(
+    test: &dyn DynTester,
+    options: &Options,
+    reader: &mut dyn BufRead,
+) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for_each_position(test, options, reader, |fen, output| {
+        result.push((fen.to_string(), digest(output)))
+    });
+    result
+}
+
+// Golden file contains one position per line, with the FEN and the digest separated by a tab.
+// Lines starting with '#' are comments.
+pub fn write_golden(
+    writer: &mut dyn Write,
+    reference: &str,
+    digests: &[(String, String)],
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "# This file is generated automatically, DO NOT EDIT!"
+    )?;
+    writeln!(writer, "#")?;
+    writeln!(
+        writer,
+        "# Digests of selftest output for each position in boards.fen, generated with:"
+    )?;
+    writeln!(writer, "# selftest golden {} <boards.fen", reference)?;
+    for (fen, digest) in digests {
+        writeln!(writer, "{}\t{}", fen, digest)?;
+    }
+    Ok(())
+}
+
+// Returns the pairs of FEN and digest from the golden file.
+pub fn parse_golden(data: &str) -> Result<Vec<(&str, &str)>, String> {
+    data.lines()
+        .enumerate()
+        .map(|(i, l)| (i, l.trim_end()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .map(|(i, l)| {
+            l.split_once('\t')
+                .ok_or_else(|| format!("line {}: no tab between fen and digest", i + 1))
+        })
+        .collect()
+}
+
+// Positions on which the selftest output doesn't match the golden file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Mismatches<'a> {
+    // Positions whose digests differ from the golden ones.
+    pub changed: Vec<&'a str>,
+    // Positions from the input which are not in the golden file.
+    pub extra: Vec<&'a str>,
+    // Positions from the golden file which are not in the input.
+    pub missing: Vec<&'a str>,
+}
+
+impl Mismatches<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.extra.is_empty() && self.missing.is_empty()
+    }
+}
+
+// Compares the digests with the golden ones, matching them by FEN, so the order of the positions
+// doesn't matter.
+pub fn mismatches<'a>(
+    digests: &'a [(String, String)],
+    golden: &[(&'a str, &'a str)],
+) -> Mismatches<'a> {
+    let expected: HashMap<_, _> = golden.iter().copied().collect();
+    let actual: HashSet<_> = digests.iter().map(|(fen, _)| fen.as_str()).collect();
+    let mut result = Mismatches::default();
+    for (fen, digest) in digests {
+        match expected.get(fen.as_str()) {
+            Some(expected) if expected != digest => result.changed.push(fen),
+            Some(_) => {}
+            None => result.extra.push(fen),
+        }
+    }
+    result.missing = golden
+        .iter()
+        .map(|&(fen, _)| fen)
+        .filter(|fen| !actual.contains(fen))
+        .collect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls;
+    use crate::selftest::Tester;
+
+    const FENS: &str = "\
+# comment
+r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4
+
+4k3/8/8/3pPp2/8/8/8/4K3 w - d6 0 1
+invalid
+";
+
+    fn options() -> Options {
+        Options {
+            big_depth: false,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_for_each_position() {
+        let mut full = Vec::new();
+        Tester::new(impls::mailbox::Test, options(), &mut full).run_many(&mut FENS.as_bytes());
+        let mut concat = Vec::new();
+        let mut fens = Vec::new();
+        for_each_position(
+            &impls::mailbox::Test,
+            &options(),
+            &mut FENS.as_bytes(),
+            |fen, output| {
+                fens.push(fen.to_string());
+                concat.extend_from_slice(output);
+            },
+        );
+        assert_eq!(fens.len(), 3);
+        assert_eq!(concat, full);
+    }
+
+    #[test]
+    fn test_golden_round_trip() {
+        let digests = digests(&impls::mailbox::Test, &options(), &mut FENS.as_bytes());
+        let mut golden = Vec::new();
+        write_golden(&mut golden, "mailbox", &digests).unwrap();
+        let golden = String::from_utf8(golden).unwrap();
+        let golden = parse_golden(&golden).unwrap();
+        assert_eq!(golden.len(), 3);
+        assert!(mismatches(&digests, &golden).is_empty());
+
+        let mut other = golden.clone();
+        other[1].1 = "0000000000000000";
+        other.swap(0, 2);
+        let expected = Mismatches {
+            changed: vec![digests[1].0.as_str()],
+            ..Default::default()
+        };
+        assert_eq!(mismatches(&digests, &other), expected);
+
+        let expected = Mismatches {
+            extra: vec!["invalid"],
+            ..Default::default()
+        };
+        assert_eq!(mismatches(&digests, &golden[..2]), expected);
+        let expected = Mismatches {
+            missing: vec!["invalid"],
+            ..Default::default()
+        };
+        assert_eq!(mismatches(&digests[..2], &golden), expected);
+
+        assert!(parse_golden("# comment\nno digest\n").is_err());
+    }
+}
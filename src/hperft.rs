@@ -0,0 +1,68 @@
+// Hperft v2 hashes the whole position, not only the occupancy of both sides as v1 does. So the
+// implementations have to convert their positions into this common representation.
+//
+// All the bitboards use the following square numbering: a1 is bit 0, b1 is bit 1, ..., h8 is
+// bit 63.
+pub(crate) struct Position {
+    pub white: u64,
+    pub black: u64,
+    // Pawns, knights, bishops, rooks, queens and kings of both sides.
+    pub pieces: [u64; 6],
+    pub white_to_move: bool,
+    // White kingside, white queenside, black kingside, black queenside.
+    pub castling: [bool; 4],
+    // Implementations differ in when they keep the en passant square, so we accept any file here
+    // and keep it only if the side to move has a pawn to capture en passant. Note that such
+    // capture is not necessarily legal.
+    pub ep_file: Option<usize>,
+}
+
+const MUL: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn mix(h: u64, x: u64) -> u64 {
+    (h.rotate_left(5) ^ x).wrapping_mul(MUL)
+}
+
+// Final mixing step from MurmurHash3, so the sums over many positions are well distributed.
+fn finalize(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+impl Position {
+    fn ep_square(&self) -> Option<usize> {
+        let file = self.ep_file?;
+        let (our, rank, dst_rank) = match self.white_to_move {
+            true => (self.white, 4, 5),
+            false => (self.black, 3, 2),
+        };
+        let pawns = self.pieces[0] & our;
+        let mut attackers = 0;
+        if file > 0 {
+            attackers |= 1 << (rank * 8 + file - 1);
+        }
+        if file < 7 {
+            attackers |= 1 << (rank * 8 + file + 1);
+        }
+        (pawns & attackers != 0).then_some(dst_rank * 8 + file)
+    }
+
+    pub fn hash(&self) -> u64 {
+        let mut state = self.white_to_move as u64;
+        for (idx, &c) in self.castling.iter().enumerate() {
+            state |= (c as u64) << (idx + 1);
+        }
+        if let Some(sq) = self.ep_square() {
+            state |= (sq as u64 + 1) << 5;
+        }
+
+        let mut h = mix(mix(0, self.white), self.black);
+        for &bb in &self.pieces {
+            h = mix(h, bb);
+        }
+        finalize(mix(h, state))
+    }
+}
@@ -1,28 +1,97 @@
-use crate::MoveNotLegal;
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
 use arrayvec::ArrayVec;
-use chess::{Board, ChessMove, Color, File, MoveGen, Piece, Rank, Square};
+use chess::{
+    BitBoard, Board, BoardStatus, ChessMove, Color, File, MoveGen, Piece, Rank, Square, ALL_PIECES,
+};
+use std::collections::BTreeMap;
 use std::mem;
 use std::str::FromStr;
 
 pub struct Perft;
+#[derive(Clone, Copy)]
 pub struct Test;
 
+const NAME: &str = "chess";
+
+fn board_from_fen(fen: &str) -> Result<Board, Error> {
+    Board::from_str(fen).map_err(|e| Error::invalid_fen(NAME, fen, e))
+}
+
+// `chess` can only parse SAN, so we write it by ourselves.
+fn move_to_san(b: &Board, mv: ChessMove) -> String {
+    let (src, dst) = (mv.get_source(), mv.get_dest());
+    let piece = b.piece_on(src).expect("no piece to move");
+    let is_capture = b.piece_on(dst).is_some();
+    let file_char = |sq: Square| (b'a' + sq.get_file().to_index() as u8) as char;
+    let rank_char = |sq: Square| (b'1' + sq.get_rank().to_index() as u8) as char;
+
+    let mut result = String::new();
+    match piece {
+        Piece::King if src.get_file() == File::E && dst.get_file() == File::G => {
+            result += "O-O";
+        }
+        Piece::King if src.get_file() == File::E && dst.get_file() == File::C => {
+            result += "O-O-O";
+        }
+        Piece::Pawn => {
+            if src.get_file() != dst.get_file() {
+                result.push(file_char(src));
+                result.push('x');
+            }
+            result += &dst.to_string();
+            if let Some(promote) = mv.get_promotion() {
+                result.push('=');
+                result += &promote.to_string(Color::White);
+            }
+        }
+        _ => {
+            result += &piece.to_string(Color::White);
+            let others: ArrayVec<Square, 16> = MoveGen::new_legal(b)
+                .filter(|m| m.get_dest() == dst && m.get_source() != src)
+                .filter(|m| b.piece_on(m.get_source()) == Some(piece))
+                .map(|m| m.get_source())
+                .collect();
+            let same_file = others.iter().any(|sq| sq.get_file() == src.get_file());
+            let same_rank = others.iter().any(|sq| sq.get_rank() == src.get_rank());
+            if !others.is_empty() && (!same_file || same_rank) {
+                result.push(file_char(src));
+            }
+            if same_file {
+                result.push(rank_char(src));
+            }
+            if is_capture {
+                result.push('x');
+            }
+            result += &dst.to_string();
+        }
+    }
+
+    let after = b.make_move_new(mv);
+    match after.status() {
+        BoardStatus::Checkmate => result.push('#'),
+        _ if after.checkers() != &chess::EMPTY => result.push('+'),
+        _ => {}
+    }
+    result
+}
+
 impl crate::Test for Test {
     type Board = Board;
     type Move = ChessMove;
     type Undo = Board;
-    type MoveList = ArrayVec<ChessMove, 256>;
 
-    fn get_move<'a>(&self, list: &'a Self::MoveList, idx: usize) -> &'a Self::Move {
-        &list[idx]
-    }
-
-    fn move_count(&self, list: &Self::MoveList) -> usize {
-        list.len()
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    fn board_from_fen(&self, fen: &str) -> Self::Board {
-        Board::from_str(fen).expect("invalid fen")
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        board_from_fen(fen)
     }
 
     fn try_make_move(
@@ -43,8 +112,8 @@ impl crate::Test for Test {
         mv.to_string()
     }
 
-    fn generate_moves(&self, b: &Self::Board) -> Self::MoveList {
-        MoveGen::new_legal(b).collect()
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
+        MoveGen::new_legal(b).for_each(visit);
     }
 
     fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
@@ -79,16 +148,60 @@ impl crate::Test for Test {
     }
 
     fn run_self_test(&self, _b: &Self::Board) {}
+
+    // `board_to_fen()` is not supported, as `chess` doesn't keep move counters.
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.status() == BoardStatus::Checkmate)
+    }
+
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.status() == BoardStatus::Stalemate)
+    }
+
+    fn is_uci_move_legal(&self, b: &Self::Board, s: &str) -> Option<bool> {
+        Some(ChessMove::from_str(s).is_ok_and(|mv| b.legal(mv)))
+    }
+
+    fn move_to_san(&self, b: &Self::Board, mv: &Self::Move) -> Option<String> {
+        Some(move_to_san(b, *mv))
+    }
+
+    fn move_from_san(&self, b: &Self::Board, s: &str) -> Option<Result<Self::Move, MoveNotLegal>> {
+        Some(ChessMove::from_san(b, s).map_err(|_| MoveNotLegal))
+    }
 }
 
 impl Perft {
-    fn do_hperft(board: &Board, depth: usize) -> u64 {
+    fn hperft_v1_leaf(board: &Board) -> u64 {
+        let white = board.color_combined(Color::White).0;
+        let black = board.color_combined(Color::Black).0;
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(board: &Board) -> u64 {
+        let rights = |c| board.castle_rights(c);
+        hperft::Position {
+            white: board.color_combined(Color::White).0,
+            black: board.color_combined(Color::Black).0,
+            pieces: ALL_PIECES.map(|p| board.pieces(p).0),
+            white_to_move: board.side_to_move() == Color::White,
+            castling: [
+                rights(Color::White).has_kingside(),
+                rights(Color::White).has_queenside(),
+                rights(Color::Black).has_kingside(),
+                rights(Color::Black).has_queenside(),
+            ],
+            ep_file: board.en_passant().map(|sq| sq.get_file().to_index()),
+        }
+        .hash()
+    }
+
+    fn do_hperft(board: &Board, depth: usize, leaf: impl Fn(&Board) -> u64 + Copy) -> u64 {
         if depth == 0 {
-            let white = board.color_combined(Color::White).0;
-            let black = board.color_combined(Color::Black).0;
-            return white
-                .wrapping_mul(crate::HPERFT_WHITE)
-                .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK));
+            return leaf(board);
         }
 
         let iterable = MoveGen::new_legal(board);
@@ -98,25 +211,150 @@ impl Perft {
             let mut bresult = mem::MaybeUninit::<Board>::uninit();
             unsafe {
                 board.make_move(m, &mut *bresult.as_mut_ptr());
-                result = result.wrapping_add(Self::do_hperft(&*bresult.as_ptr(), depth - 1));
+                result = result.wrapping_add(Self::do_hperft(&*bresult.as_ptr(), depth - 1, leaf));
             }
         }
         result
     }
+
+    fn do_hashed_perft(board: &Board, depth: usize, table: &mut PerftTable) -> u64 {
+        match depth {
+            0 => return 1,
+            1 => return MoveGen::new_legal(board).len() as u64,
+            _ => {}
+        }
+
+        let hash = board.get_hash();
+        if let Some(count) = table.get(hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        for m in MoveGen::new_legal(board) {
+            count += Self::do_hashed_perft(&board.make_move_new(m), depth - 1, table);
+        }
+        table.insert(hash, depth, count);
+        count
+    }
+
+    fn do_verify_hash(
+        board: &Board,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = board.to_string();
+        let rebuilt = board_from_fen(&fen)?.get_hash();
+        if let Some(m) = HashMismatch::check(NAME, path, fen, board.get_hash(), rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        for m in MoveGen::new_legal(board) {
+            path.push(m.to_string());
+            if let Some(m) = Self::do_verify_hash(&board.make_move_new(m), depth - 1, path)? {
+                return Ok(Some(m));
+            }
+            path.pop();
+        }
+        Ok(None)
+    }
+
+    fn do_perft_stats(board: &Board, depth: usize, stats: &mut PerftStats) {
+        for m in MoveGen::new_legal(board) {
+            let next = board.make_move_new(m);
+            if depth > 1 {
+                Self::do_perft_stats(&next, depth - 1, stats);
+                continue;
+            }
+            let (src, dst) = (m.get_source(), m.get_dest());
+            let is_pawn = board.piece_on(src) == Some(Piece::Pawn);
+            let is_king = board.piece_on(src) == Some(Piece::King);
+            let en_passant =
+                is_pawn && src.get_file() != dst.get_file() && board.piece_on(dst).is_none();
+            let castle = is_king
+                && src
+                    .get_file()
+                    .to_index()
+                    .abs_diff(dst.get_file().to_index())
+                    == 2;
+            stats.add_leaf(Leaf {
+                capture: board.piece_on(dst).is_some() || en_passant,
+                en_passant,
+                castle,
+                promotion: m.get_promotion().is_some(),
+                checkers: next.checkers().0,
+                moved: if castle {
+                    !0
+                } else {
+                    BitBoard::from_square(dst).0
+                },
+                has_moves: MoveGen::new_legal(&next).len() != 0,
+            });
+        }
+    }
 }
 
 impl crate::Perft for Perft {
     fn name(&self) -> &'static str {
-        "chess"
+        NAME
     }
 
-    fn perft(&self, fen: &str, depth: usize) -> u64 {
-        let board = Board::from_str(fen).expect("invalid fen");
-        MoveGen::movegen_perft_test(&board, depth) as u64
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(MoveGen::movegen_perft_test(&board, depth) as u64)
+    }
+
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v1_leaf))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let board = board_from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        for m in MoveGen::new_legal(&board) {
+            let count = match depth {
+                1 => 1,
+                _ => MoveGen::movegen_perft_test(&board.make_move_new(m), depth - 1) as u64,
+            };
+            result.insert(m.to_string(), count);
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let board = board_from_fen(fen)?;
+        match ChessMove::from_str(mv) {
+            Ok(m) if board.legal(m) => Ok(board.make_move_new(m).to_string()),
+            _ => Err(Error::illegal_move(NAME, fen, mv)),
+        }
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&board, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let board = board_from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&board, depth, &mut stats),
+        }
+        Ok(stats)
     }
 
-    fn hperft(&self, fen: &str, depth: usize) -> u64 {
-        let board = Board::from_str(fen).expect("invalid fen");
-        Self::do_hperft(&board, depth)
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let board = board_from_fen(fen)?;
+        Self::do_verify_hash(&board, depth, &mut Vec::new())
     }
 }
@@ -1,6 +1,248 @@
-use cozy_chess::{Board, Color};
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
+use cozy_chess::{BitBoard, Board, Color, File, GameStatus, Move, Piece, Rank, Square};
+use std::collections::BTreeMap;
 
 pub struct Perft;
+#[derive(Clone, Copy)]
+pub struct Test;
+
+const NAME: &str = "cozy_chess";
+
+fn board_from_fen(fen: &str) -> Result<Board, Error> {
+    Board::from_fen(fen, false).map_err(|e| Error::invalid_fen(NAME, fen, e))
+}
+
+// cozy_chess encodes castling as "king captures rook", so we convert such moves into the usual
+// UCI notation.
+fn uci_move(board: &Board, mv: Move) -> Move {
+    if board.piece_on(mv.from) == Some(Piece::King) && board.colors(board.side_to_move()).has(mv.to)
+    {
+        let file = if mv.from.file() < mv.to.file() {
+            File::G
+        } else {
+            File::C
+        };
+        let to = Square::new(file, mv.from.rank());
+        return Move { to, ..mv };
+    }
+    mv
+}
+
+fn move_str(board: &Board, mv: Move) -> String {
+    uci_move(board, mv).to_string()
+}
+
+// Reverse of `uci_move()`: converts the king's two-square move into "king captures rook".
+fn parse_uci(board: &Board, s: &str) -> Option<Move> {
+    let mut mv: Move = s.parse().ok()?;
+    if board.piece_on(mv.from) == Some(Piece::King)
+        && mv.from.rank() == mv.to.rank()
+        && (mv.from.file() as i32 - mv.to.file() as i32).abs() == 2
+    {
+        let file = if mv.from.file() < mv.to.file() {
+            File::H
+        } else {
+            File::A
+        };
+        mv.to = Square::new(file, mv.from.rank());
+    }
+    // The check rejects the moves like "e1h1", which are legal only in cozy_chess's own notation.
+    (board.is_legal(mv) && move_str(board, mv) == s).then_some(mv)
+}
+
+// cozy_chess doesn't support SAN, so we write and parse it by ourselves.
+fn move_to_san(board: &Board, mv: Move) -> String {
+    let piece = board.piece_on(mv.from).expect("no piece to move");
+    let is_capture = board.colors(!board.side_to_move()).has(mv.to);
+    let upper = |p: Piece| char::from(p).to_ascii_uppercase();
+
+    let mut result = String::new();
+    match piece {
+        Piece::King if board.colors(board.side_to_move()).has(mv.to) => {
+            result += if mv.from.file() < mv.to.file() {
+                "O-O"
+            } else {
+                "O-O-O"
+            };
+        }
+        Piece::Pawn => {
+            if mv.from.file() != mv.to.file() {
+                result.push(mv.from.file().into());
+                result.push('x');
+            }
+            result += &mv.to.to_string();
+            if let Some(promotion) = mv.promotion {
+                result.push('=');
+                result.push(upper(promotion));
+            }
+        }
+        _ => {
+            result.push(upper(piece));
+            let mut others = BitBoard::EMPTY;
+            board.generate_moves_for(board.pieces(piece), |moves| {
+                if moves.from != mv.from && moves.to.has(mv.to) {
+                    others |= moves.from.bitboard();
+                }
+                false
+            });
+            let same_file = !(others & mv.from.file().bitboard()).is_empty();
+            let same_rank = !(others & mv.from.rank().bitboard()).is_empty();
+            if !others.is_empty() && (!same_file || same_rank) {
+                result.push(mv.from.file().into());
+            }
+            if same_file {
+                result.push(mv.from.rank().into());
+            }
+            if is_capture {
+                result.push('x');
+            }
+            result += &mv.to.to_string();
+        }
+    }
+
+    let mut after = board.clone();
+    after.play_unchecked(mv);
+    match after.status() {
+        GameStatus::Won => result.push('#'),
+        _ if !after.checkers().is_empty() => result.push('+'),
+        _ => {}
+    }
+    result
+}
+
+fn move_from_san(board: &Board, s: &str) -> Option<Move> {
+    // Check and mate marks are optional in the input.
+    let s = s.trim_end_matches(['+', '#']);
+    let mut found = None;
+    board.generate_moves(|moves| {
+        found = moves
+            .into_iter()
+            .find(|&mv| move_to_san(board, mv).trim_end_matches(['+', '#']) == s);
+        found.is_some()
+    });
+    found
+}
+
+// cozy_chess keeps the en passant square after each double pawn push, so we remove it from FEN if
+// en passant capture is not legal.
+fn normalized_fen(board: &Board) -> String {
+    let fen = board.to_string();
+    let Some(file) = board.en_passant() else {
+        return fen;
+    };
+    let ep = Square::new(file, Rank::Sixth.relative_to(board.side_to_move()));
+    match board.generate_moves(|moves| moves.piece == Piece::Pawn && moves.to.has(ep)) {
+        true => fen,
+        false => super::fen_without_ep(&fen),
+    }
+}
+
+// The move is stored along with its UCI notation, as converting castling moves requires the board.
+#[derive(Clone, Copy)]
+pub struct TestMove {
+    mv: Move,
+    uci: Move,
+}
+
+impl crate::Test for Test {
+    type Board = Board;
+    type Move = TestMove;
+    type Undo = Board;
+
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        board_from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        let old = board.clone();
+        board.play_unchecked(mv.mv);
+        Ok(old)
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, _mv: &Self::Move, u: &Self::Undo) {
+        *board = u.clone();
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        mv.uci.to_string()
+    }
+
+    fn generate_moves(&self, b: &Self::Board, mut visit: impl FnMut(Self::Move)) {
+        b.generate_moves(|moves| {
+            for mv in moves {
+                visit(TestMove {
+                    mv,
+                    uci: uci_move(b, mv),
+                });
+            }
+            false
+        });
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        let color = if is_white { Color::White } else { Color::Black };
+        let sq = Square::new(
+            File::index(cx as usize - 'a' as usize),
+            Rank::index(cy as usize - '1' as usize),
+        );
+        let pieces = |p| b.colored_pieces(color, p);
+        let attackers = (cozy_chess::get_knight_moves(sq) & pieces(Piece::Knight))
+            | (cozy_chess::get_king_moves(sq) & pieces(Piece::King))
+            | (cozy_chess::get_pawn_attacks(sq, !color) & pieces(Piece::Pawn))
+            | (cozy_chess::get_bishop_moves(sq, b.occupied())
+                & (pieces(Piece::Bishop) | pieces(Piece::Queen)))
+            | (cozy_chess::get_rook_moves(sq, b.occupied())
+                & (pieces(Piece::Rook) | pieces(Piece::Queen)));
+        attackers != BitBoard::EMPTY
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        !b.checkers().is_empty()
+    }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        Some(normalized_fen(b))
+    }
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.status() == GameStatus::Won)
+    }
+
+    // `GameStatus::Drawn` also includes the fifty-move rule, so it cannot be used here.
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.checkers().is_empty() && !b.generate_moves(|_| true))
+    }
+
+    fn is_uci_move_legal(&self, b: &Self::Board, s: &str) -> Option<bool> {
+        Some(parse_uci(b, s).is_some())
+    }
+
+    fn move_to_san(&self, b: &Self::Board, mv: &Self::Move) -> Option<String> {
+        Some(move_to_san(b, mv.mv))
+    }
+
+    fn move_from_san(&self, b: &Self::Board, s: &str) -> Option<Result<Self::Move, MoveNotLegal>> {
+        let mv = move_from_san(b, s).ok_or(MoveNotLegal);
+        Some(mv.map(|mv| TestMove {
+            mv,
+            uci: uci_move(b, mv),
+        }))
+    }
+}
 
 impl Perft {
     fn do_perft(board: &Board, depth: usize) -> u64 {
@@ -25,13 +267,35 @@ impl Perft {
         count
     }
 
-    fn do_hperft(board: &Board, depth: usize) -> u64 {
+    fn hperft_v1_leaf(board: &Board) -> u64 {
+        let white = board.colors(Color::White).0;
+        let black = board.colors(Color::Black).0;
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(board: &Board) -> u64 {
+        let rights = |c| board.castle_rights(c);
+        hperft::Position {
+            white: board.colors(Color::White).0,
+            black: board.colors(Color::Black).0,
+            pieces: Piece::ALL.map(|p| board.pieces(p).0),
+            white_to_move: board.side_to_move() == Color::White,
+            castling: [
+                rights(Color::White).short.is_some(),
+                rights(Color::White).long.is_some(),
+                rights(Color::Black).short.is_some(),
+                rights(Color::Black).long.is_some(),
+            ],
+            ep_file: board.en_passant().map(|f| f as usize),
+        }
+        .hash()
+    }
+
+    fn do_hperft(board: &Board, depth: usize, leaf: impl Fn(&Board) -> u64 + Copy) -> u64 {
         if depth == 0 {
-            let white = board.colors(Color::White).0;
-            let black = board.colors(Color::Black).0;
-            return white
-                .wrapping_mul(crate::HPERFT_WHITE)
-                .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK));
+            return leaf(board);
         }
 
         let mut count: u64 = 0;
@@ -39,26 +303,179 @@ impl Perft {
             for mv in moves {
                 let mut child = board.clone();
                 child.play_unchecked(mv);
-                count = count.wrapping_add(Self::do_hperft(&child, depth - 1));
+                count = count.wrapping_add(Self::do_hperft(&child, depth - 1, leaf));
+            }
+            false
+        });
+        count
+    }
+
+    fn do_hashed_perft(board: &Board, depth: usize, table: &mut PerftTable) -> u64 {
+        match depth {
+            0 => return 1,
+            1 => return Self::do_perft(board, 1),
+            _ => {}
+        }
+
+        let hash = board.hash();
+        if let Some(count) = table.get(hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        board.generate_moves(|moves| {
+            for mv in moves {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                count += Self::do_hashed_perft(&child, depth - 1, table);
             }
             false
         });
+        table.insert(hash, depth, count);
         count
     }
+
+    fn do_verify_hash(
+        board: &Board,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = board.to_string();
+        let rebuilt = board_from_fen(&fen)?.hash();
+        if let Some(m) = HashMismatch::check(NAME, path, fen, board.hash(), rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        let mut result = Ok(None);
+        board.generate_moves(|moves| {
+            for mv in moves {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                path.push(move_str(board, mv));
+                result = Self::do_verify_hash(&child, depth - 1, path);
+                if !matches!(result, Ok(None)) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        });
+        result
+    }
+
+    fn do_perft_stats(board: &Board, depth: usize, stats: &mut PerftStats) {
+        board.generate_moves(|moves| {
+            for mv in moves {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                if depth > 1 {
+                    Self::do_perft_stats(&child, depth - 1, stats);
+                    continue;
+                }
+                let piece = board.piece_on(mv.from);
+                let en_passant = piece == Some(Piece::Pawn)
+                    && mv.from.file() != mv.to.file()
+                    && board.piece_on(mv.to).is_none();
+                let castle =
+                    piece == Some(Piece::King) && board.colors(board.side_to_move()).has(mv.to);
+                stats.add_leaf(Leaf {
+                    capture: board.colors(!board.side_to_move()).has(mv.to) || en_passant,
+                    en_passant,
+                    castle,
+                    promotion: mv.promotion.is_some(),
+                    checkers: child.checkers().0,
+                    moved: if castle { !0 } else { mv.to.bitboard().0 },
+                    has_moves: child.generate_moves(|_| true),
+                });
+            }
+            false
+        });
+    }
 }
 
 impl crate::Perft for Perft {
     fn name(&self) -> &'static str {
-        "cozy_chess"
+        NAME
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_perft(&board, depth))
+    }
+
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v1_leaf))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let board = board_from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        board.generate_moves(|moves| {
+            for mv in moves {
+                let mut child = board.clone();
+                child.play_unchecked(mv);
+                let count = match depth {
+                    1 => 1,
+                    _ => Self::do_perft(&child, depth - 1),
+                };
+                result.insert(move_str(&board, mv), count);
+            }
+            false
+        });
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let board = board_from_fen(fen)?;
+        let mut found = None;
+        board.generate_moves(|moves| {
+            found = moves.into_iter().find(|&m| move_str(&board, m) == mv);
+            found.is_some()
+        });
+        let found = found.ok_or_else(|| Error::illegal_move(NAME, fen, mv))?;
+        let mut child = board.clone();
+        child.play_unchecked(found);
+        Ok(child.to_string())
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&board, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let board = board_from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&board, depth, &mut stats),
+        }
+        Ok(stats)
+    }
+
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let board = board_from_fen(fen)?;
+        Self::do_verify_hash(&board, depth, &mut Vec::new())
     }
 
-    fn perft(&self, fen: &str, depth: usize) -> u64 {
-        let board = Board::from_fen(fen, false).expect("invalid fen");
-        Self::do_perft(&board, depth)
+    fn supports_chess960(&self) -> bool {
+        true
     }
 
-    fn hperft(&self, fen: &str, depth: usize) -> u64 {
-        let board = Board::from_fen(fen, false).expect("invalid fen");
-        Self::do_hperft(&board, depth)
+    fn perft_chess960(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        // cozy_chess doesn't understand X-FEN, so we convert castling rights into Shredder-FEN.
+        let fen = super::shredder_fen(fen);
+        let board = Board::from_fen(&fen, true).map_err(|e| Error::invalid_fen(NAME, &fen, e))?;
+        Ok(Self::do_perft(&board, depth))
     }
 }
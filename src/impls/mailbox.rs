@@ -0,0 +1,1019 @@
+// Deliberately simple move generator on a 10x12 mailbox, which doesn't depend on any external
+// crate. It prefers obvious code over speed, and serves as a reference to check the other
+// implementations against.
+
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
+use std::collections::BTreeMap;
+
+pub struct Perft;
+#[derive(Clone, Copy)]
+pub struct Test;
+
+const NAME: &str = "mailbox";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    fn inv(self) -> Self {
+        match self {
+            Self::White => Self::Black,
+            Self::Black => Self::White,
+        }
+    }
+}
+
+// The order is the same as in `hperft::Position::pieces`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl Kind {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'p' => Some(Self::Pawn),
+            'n' => Some(Self::Knight),
+            'b' => Some(Self::Bishop),
+            'r' => Some(Self::Rook),
+            'q' => Some(Self::Queen),
+            'k' => Some(Self::King),
+            _ => None,
+        }
+    }
+
+    fn as_char(self) -> char {
+        match self {
+            Self::Pawn => 'p',
+            Self::Knight => 'n',
+            Self::Bishop => 'b',
+            Self::Rook => 'r',
+            Self::Queen => 'q',
+            Self::King => 'k',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Offboard,
+    Empty,
+    Piece(Color, Kind),
+}
+
+// Squares are numbered as in 10x12 mailbox, i.e. a1 is 21, h1 is 28, a8 is 91 and h8 is 98. Two
+// rows of offboard cells above and below the board are enough to stop knight jumps.
+fn square(file: usize, rank: usize) -> usize {
+    21 + file + 10 * rank
+}
+
+fn file_of(sq: usize) -> usize {
+    sq % 10 - 1
+}
+
+fn rank_of(sq: usize) -> usize {
+    sq / 10 - 2
+}
+
+fn square_bit(sq: usize) -> u64 {
+    1 << (rank_of(sq) * 8 + file_of(sq))
+}
+
+fn square_name(sq: usize) -> String {
+    format!(
+        "{}{}",
+        (b'a' + file_of(sq) as u8) as char,
+        (b'1' + rank_of(sq) as u8) as char
+    )
+}
+
+fn parse_square(s: &str) -> Option<usize> {
+    match s.as_bytes() {
+        &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+            Some(square((file - b'a') as usize, (rank - b'1') as usize))
+        }
+        _ => None,
+    }
+}
+
+fn all_squares() -> impl Iterator<Item = usize> {
+    (0..8).flat_map(|rank| (0..8).map(move |file| square(file, rank)))
+}
+
+const KNIGHT_STEPS: [isize; 8] = [-21, -19, -12, -8, 8, 12, 19, 21];
+const KING_STEPS: [isize; 8] = [-11, -10, -9, -1, 1, 9, 10, 11];
+const BISHOP_STEPS: [isize; 4] = [-11, -9, 9, 11];
+const ROOK_STEPS: [isize; 4] = [-10, -1, 1, 10];
+
+const A1: usize = 21;
+const E1: usize = 25;
+const H1: usize = 28;
+const A8: usize = 91;
+const E8: usize = 95;
+const H8: usize = 98;
+
+// Castling rights in the order white kingside, white queenside, black kingside, black queenside,
+// with the squares of the king and the rook. The rights are lost when anything moves from or to
+// one of these squares.
+const CASTLING: [(usize, usize); 4] = [(E1, H1), (E1, A1), (E8, H8), (E8, A8)];
+
+// Zobrist keys are generated by SplitMix64, so we don't need to store them.
+fn zobrist_key(idx: u64) -> u64 {
+    let mut z = idx.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn piece_key(color: Color, kind: Kind, sq: usize) -> u64 {
+    let sq = (rank_of(sq) * 8 + file_of(sq)) as u64;
+    zobrist_key(((color as u64) * 6 + kind as u64) * 64 + sq)
+}
+
+fn side_key() -> u64 {
+    zobrist_key(768)
+}
+
+fn castling_key(idx: usize) -> u64 {
+    zobrist_key(769 + idx as u64)
+}
+
+fn ep_key(sq: usize) -> u64 {
+    zobrist_key(773 + file_of(sq) as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    from: usize,
+    to: usize,
+    promote: Option<Kind>,
+}
+
+impl Move {
+    fn uci(&self) -> String {
+        let mut result = square_name(self.from) + &square_name(self.to);
+        if let Some(kind) = self.promote {
+            result.push(kind.as_char());
+        }
+        result
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    cells: [Cell; 120],
+    side: Color,
+    castling: [bool; 4],
+    // Square skipped by the pawn on the last double move, even if en passant is not possible.
+    ep: Option<usize>,
+    halfmove: u32,
+    fullmove: u32,
+    hash: u64,
+    // King squares of white and black.
+    kings: [usize; 2],
+}
+
+impl Board {
+    fn from_fen(fen: &str) -> Result<Self, Error> {
+        let bad_fen = |reason: &str| Error::invalid_fen(NAME, fen, reason);
+        let bad_setup = |reason: &str| Error::invalid_setup(NAME, fen, reason);
+
+        let parts: Vec<_> = fen.split(' ').collect();
+        if parts.len() != 6 {
+            return Err(bad_fen("expected 6 fields"));
+        }
+
+        let mut cells = [Cell::Offboard; 120];
+        let rows: Vec<_> = parts[0].split('/').collect();
+        if rows.len() != 8 {
+            return Err(bad_fen("expected 8 ranks"));
+        }
+        for (idx, row) in rows.iter().enumerate() {
+            let rank = 7 - idx;
+            let mut file = 0;
+            for c in row.chars() {
+                if let Some(skip) = c.to_digit(10).filter(|d| (1..=8).contains(d)) {
+                    for _ in 0..skip {
+                        if file < 8 {
+                            cells[square(file, rank)] = Cell::Empty;
+                        }
+                        file += 1;
+                    }
+                    continue;
+                }
+                let kind =
+                    Kind::from_char(c.to_ascii_lowercase()).ok_or_else(|| bad_fen("bad piece"))?;
+                let color = match c.is_ascii_uppercase() {
+                    true => Color::White,
+                    false => Color::Black,
+                };
+                if file < 8 {
+                    cells[square(file, rank)] = Cell::Piece(color, kind);
+                }
+                file += 1;
+            }
+            if file != 8 {
+                return Err(bad_fen("bad rank length"));
+            }
+        }
+
+        let side = match parts[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(bad_fen("bad side to move")),
+        };
+
+        let mut castling = [false; 4];
+        if parts[2] != "-" {
+            for c in parts[2].chars() {
+                let idx = "KQkq".find(c).ok_or_else(|| bad_fen("bad castling"))?;
+                if castling[idx] {
+                    return Err(bad_fen("bad castling"));
+                }
+                castling[idx] = true;
+            }
+        }
+
+        let ep = match parts[3] {
+            "-" => None,
+            s => Some(parse_square(s).ok_or_else(|| bad_fen("bad en passant square"))?),
+        };
+
+        let halfmove = parts[4]
+            .parse()
+            .map_err(|_| bad_fen("bad halfmove clock"))?;
+        let fullmove = parts[5].parse().map_err(|_| bad_fen("bad move number"))?;
+
+        let mut board = Board {
+            cells,
+            side,
+            castling,
+            ep,
+            halfmove,
+            fullmove,
+            hash: 0,
+            kings: [0; 2],
+        };
+        board.hash = board.calc_hash();
+
+        for color in [Color::White, Color::Black] {
+            let kings = all_squares()
+                .filter(|&sq| board.cells[sq] == Cell::Piece(color, Kind::King))
+                .count();
+            if kings != 1 {
+                return Err(bad_setup("expected exactly one king of each color"));
+            }
+            board.kings[color as usize] = all_squares()
+                .find(|&sq| board.cells[sq] == Cell::Piece(color, Kind::King))
+                .unwrap();
+        }
+        let pawn_on_back_rank = all_squares().any(|sq| {
+            matches!(board.cells[sq], Cell::Piece(_, Kind::Pawn)) && matches!(rank_of(sq), 0 | 7)
+        });
+        if pawn_on_back_rank {
+            return Err(bad_setup("pawn on the back rank"));
+        }
+        for (idx, &(king, rook)) in CASTLING.iter().enumerate() {
+            let color = if idx < 2 { Color::White } else { Color::Black };
+            if castling[idx]
+                && (board.cells[king] != Cell::Piece(color, Kind::King)
+                    || board.cells[rook] != Cell::Piece(color, Kind::Rook))
+            {
+                return Err(bad_setup("bad castling rights"));
+            }
+        }
+        if let Some(ep) = ep {
+            let (rank, fwd) = match side {
+                Color::White => (5, 10),
+                Color::Black => (2, -10),
+            };
+            let pawn = board.cells[ep.wrapping_add_signed(-fwd)];
+            if rank_of(ep) != rank
+                || board.cells[ep] != Cell::Empty
+                || board.cells[ep.wrapping_add_signed(fwd)] != Cell::Empty
+                || pawn != Cell::Piece(side.inv(), Kind::Pawn)
+            {
+                return Err(bad_setup("bad en passant square"));
+            }
+        }
+        if board.is_attacked(board.king(side.inv()), side) {
+            return Err(bad_setup("the side which is not to move is in check"));
+        }
+
+        Ok(board)
+    }
+
+    fn fen_with_ep(&self, ep: Option<usize>) -> String {
+        let mut rows = Vec::new();
+        for rank in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.cells[square(file, rank)] {
+                    Cell::Piece(color, kind) => {
+                        if empty != 0 {
+                            row += &empty.to_string();
+                            empty = 0;
+                        }
+                        row.push(match color {
+                            Color::White => kind.as_char().to_ascii_uppercase(),
+                            Color::Black => kind.as_char(),
+                        });
+                    }
+                    _ => empty += 1,
+                }
+            }
+            if empty != 0 {
+                row += &empty.to_string();
+            }
+            rows.push(row);
+        }
+
+        let castling: String = "KQkq"
+            .chars()
+            .zip(self.castling)
+            .filter(|&(_, allowed)| allowed)
+            .map(|(c, _)| c)
+            .collect();
+        format!(
+            "{} {} {} {} {} {}",
+            rows.join("/"),
+            if self.side == Color::White { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { &castling },
+            ep.map_or("-".to_string(), square_name),
+            self.halfmove,
+            self.fullmove
+        )
+    }
+
+    // FEN with the en passant square written as is.
+    fn fen(&self) -> String {
+        self.fen_with_ep(self.ep)
+    }
+
+    // FEN with the en passant square written only if en passant capture is legal.
+    fn normalized_fen(&self) -> String {
+        let has_ep = self.legal_moves().iter().any(|&mv| self.is_en_passant(mv));
+        self.fen_with_ep(self.ep.filter(|_| has_ep))
+    }
+
+    fn calc_hash(&self) -> u64 {
+        let mut hash = 0;
+        for sq in all_squares() {
+            if let Cell::Piece(color, kind) = self.cells[sq] {
+                hash ^= piece_key(color, kind, sq);
+            }
+        }
+        if self.side == Color::Black {
+            hash ^= side_key();
+        }
+        for (idx, &allowed) in self.castling.iter().enumerate() {
+            if allowed {
+                hash ^= castling_key(idx);
+            }
+        }
+        if let Some(ep) = self.ep {
+            hash ^= ep_key(ep);
+        }
+        hash
+    }
+
+    fn set(&mut self, sq: usize, cell: Cell) {
+        if let Cell::Piece(color, kind) = self.cells[sq] {
+            self.hash ^= piece_key(color, kind, sq);
+        }
+        if let Cell::Piece(color, kind) = cell {
+            self.hash ^= piece_key(color, kind, sq);
+            if kind == Kind::King {
+                self.kings[color as usize] = sq;
+            }
+        }
+        self.cells[sq] = cell;
+    }
+
+    fn king(&self, color: Color) -> usize {
+        self.kings[color as usize]
+    }
+
+    fn forward(color: Color) -> isize {
+        match color {
+            Color::White => 10,
+            Color::Black => -10,
+        }
+    }
+
+    // Returns the bitboard of pieces of color `by` which attack the square `sq`.
+    fn attackers(&self, sq: usize, by: Color) -> u64 {
+        let mut result = 0;
+        let mut add_if = |from: usize, kinds: &[Kind]| {
+            if let Cell::Piece(color, kind) = self.cells[from] {
+                if color == by && kinds.contains(&kind) {
+                    result |= square_bit(from);
+                }
+            }
+        };
+
+        // Pawns of color `by` attack `sq` from behind.
+        let back = -Self::forward(by);
+        add_if(sq.wrapping_add_signed(back - 1), &[Kind::Pawn]);
+        add_if(sq.wrapping_add_signed(back + 1), &[Kind::Pawn]);
+        for step in KNIGHT_STEPS {
+            add_if(sq.wrapping_add_signed(step), &[Kind::Knight]);
+        }
+        for step in KING_STEPS {
+            add_if(sq.wrapping_add_signed(step), &[Kind::King]);
+        }
+        for (steps, kinds) in [
+            (BISHOP_STEPS, [Kind::Bishop, Kind::Queen]),
+            (ROOK_STEPS, [Kind::Rook, Kind::Queen]),
+        ] {
+            for step in steps {
+                let mut cur = sq.wrapping_add_signed(step);
+                while self.cells[cur] == Cell::Empty {
+                    cur = cur.wrapping_add_signed(step);
+                }
+                add_if(cur, &kinds);
+            }
+        }
+        result
+    }
+
+    fn is_attacked(&self, sq: usize, by: Color) -> bool {
+        self.attackers(sq, by) != 0
+    }
+
+    fn checkers(&self) -> u64 {
+        self.attackers(self.king(self.side), self.side.inv())
+    }
+
+    fn is_check(&self) -> bool {
+        self.checkers() != 0
+    }
+
+    fn is_capture(&self, mv: Move) -> bool {
+        matches!(self.cells[mv.to], Cell::Piece(..)) || self.is_en_passant(mv)
+    }
+
+    fn is_en_passant(&self, mv: Move) -> bool {
+        matches!(self.cells[mv.from], Cell::Piece(_, Kind::Pawn)) && Some(mv.to) == self.ep
+    }
+
+    fn is_castling(&self, mv: Move) -> bool {
+        matches!(self.cells[mv.from], Cell::Piece(_, Kind::King)) && mv.from.abs_diff(mv.to) == 2
+    }
+
+    // Adds the moves of the piece on `from`, which may leave the king in check.
+    fn add_moves_from(&self, from: usize, moves: &mut Vec<Move>) {
+        let Cell::Piece(color, kind) = self.cells[from] else {
+            return;
+        };
+        if color != self.side {
+            return;
+        }
+        let is_enemy = |sq: usize| matches!(self.cells[sq], Cell::Piece(c, _) if c != color);
+        let mut add = |to: usize, promote: Option<Kind>| moves.push(Move { from, to, promote });
+
+        match kind {
+            Kind::Pawn => {
+                let fwd = Self::forward(color);
+                let mut add_pawn = |to: usize| {
+                    if matches!(rank_of(to), 0 | 7) {
+                        for promote in [Kind::Knight, Kind::Bishop, Kind::Rook, Kind::Queen] {
+                            add(to, Some(promote));
+                        }
+                    } else {
+                        add(to, None);
+                    }
+                };
+                let one = from.wrapping_add_signed(fwd);
+                if self.cells[one] == Cell::Empty {
+                    add_pawn(one);
+                    let two = one.wrapping_add_signed(fwd);
+                    let start_rank = if color == Color::White { 1 } else { 6 };
+                    if rank_of(from) == start_rank && self.cells[two] == Cell::Empty {
+                        add_pawn(two);
+                    }
+                }
+                for side in [-1, 1] {
+                    let to = one.wrapping_add_signed(side);
+                    if is_enemy(to) || (Some(to) == self.ep && self.cells[to] == Cell::Empty) {
+                        add_pawn(to);
+                    }
+                }
+            }
+            Kind::Knight | Kind::King => {
+                let steps = if kind == Kind::Knight {
+                    KNIGHT_STEPS
+                } else {
+                    KING_STEPS
+                };
+                for step in steps {
+                    let to = from.wrapping_add_signed(step);
+                    if self.cells[to] == Cell::Empty || is_enemy(to) {
+                        add(to, None);
+                    }
+                }
+                if kind == Kind::King {
+                    self.add_castling(|to| add(to, None));
+                }
+            }
+            Kind::Bishop | Kind::Rook | Kind::Queen => {
+                let steps: &[isize] = match kind {
+                    Kind::Bishop => &BISHOP_STEPS,
+                    Kind::Rook => &ROOK_STEPS,
+                    _ => &KING_STEPS,
+                };
+                for &step in steps {
+                    let mut to = from.wrapping_add_signed(step);
+                    while self.cells[to] == Cell::Empty {
+                        add(to, None);
+                        to = to.wrapping_add_signed(step);
+                    }
+                    if is_enemy(to) {
+                        add(to, None);
+                    }
+                }
+            }
+        }
+    }
+
+    // Adds castling moves. The king must not be in check and must not pass through an attacked
+    // square. Whether it lands on an attacked square is checked later, as for the other moves.
+    fn add_castling(&self, mut add: impl FnMut(usize)) {
+        let first = if self.side == Color::White { 0 } else { 2 };
+        for (&(king, rook), &allowed) in CASTLING.iter().zip(&self.castling).skip(first).take(2) {
+            if !allowed {
+                continue;
+            }
+            let step: isize = if rook > king { 1 } else { -1 };
+            let mut between = king.wrapping_add_signed(step);
+            let mut all_empty = true;
+            while between != rook {
+                all_empty &= self.cells[between] == Cell::Empty;
+                between = between.wrapping_add_signed(step);
+            }
+            let passed = king.wrapping_add_signed(step);
+            let enemy = self.side.inv();
+            if all_empty && !self.is_attacked(king, enemy) && !self.is_attacked(passed, enemy) {
+                add(king.wrapping_add_signed(2 * step));
+            }
+        }
+    }
+
+    fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for sq in all_squares() {
+            self.add_moves_from(sq, &mut moves);
+        }
+        moves
+    }
+
+    // Makes the move without checking whether it's legal.
+    fn make_move(&mut self, mv: Move) {
+        let Cell::Piece(color, kind) = self.cells[mv.from] else {
+            panic!("no piece to move");
+        };
+        let is_en_passant = self.is_en_passant(mv);
+        let is_castling = self.is_castling(mv);
+
+        self.halfmove += 1;
+        if kind == Kind::Pawn || self.cells[mv.to] != Cell::Empty {
+            self.halfmove = 0;
+        }
+        if let Some(ep) = self.ep.take() {
+            self.hash ^= ep_key(ep);
+        }
+
+        self.set(mv.from, Cell::Empty);
+        self.set(mv.to, Cell::Piece(color, mv.promote.unwrap_or(kind)));
+        if is_en_passant {
+            self.set(
+                mv.to.wrapping_add_signed(-Self::forward(color)),
+                Cell::Empty,
+            );
+        }
+        if is_castling {
+            let (rook_from, rook_to) = match mv.to > mv.from {
+                true => (mv.from + 3, mv.from + 1),
+                false => (mv.from - 4, mv.from - 1),
+            };
+            self.set(rook_from, Cell::Empty);
+            self.set(rook_to, Cell::Piece(color, Kind::Rook));
+        }
+        if kind == Kind::Pawn && mv.from.abs_diff(mv.to) == 20 {
+            let ep = (mv.from + mv.to) / 2;
+            self.ep = Some(ep);
+            self.hash ^= ep_key(ep);
+        }
+
+        for (idx, &(king, rook)) in CASTLING.iter().enumerate() {
+            let touched = [king, rook].iter().any(|&sq| sq == mv.from || sq == mv.to);
+            if touched && self.castling[idx] {
+                self.castling[idx] = false;
+                self.hash ^= castling_key(idx);
+            }
+        }
+
+        if color == Color::Black {
+            self.fullmove += 1;
+        }
+        self.side = color.inv();
+        self.hash ^= side_key();
+    }
+
+    // Makes the move and returns the new position if the move doesn't leave the king in check.
+    fn try_make_move(&self, mv: Move) -> Option<Board> {
+        let mut result = *self;
+        result.make_move(mv);
+        (!result.is_attacked(result.king(self.side), result.side)).then_some(result)
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|&mv| self.try_make_move(mv).is_some())
+            .collect()
+    }
+
+    fn has_legal_moves(&self) -> bool {
+        let mut moves = Vec::new();
+        all_squares().any(|sq| {
+            moves.clear();
+            self.add_moves_from(sq, &mut moves);
+            moves.iter().any(|&mv| self.try_make_move(mv).is_some())
+        })
+    }
+
+    fn parse_uci(&self, s: &str) -> Option<Move> {
+        if !matches!(s.len(), 4 | 5) || !s.is_ascii() {
+            return None;
+        }
+        let from = parse_square(&s[0..2])?;
+        let to = parse_square(&s[2..4])?;
+        let promote = match s.len() {
+            5 => Some(Kind::from_char(s.as_bytes()[4] as char)?),
+            _ => None,
+        };
+        let mut moves = Vec::new();
+        self.add_moves_from(from, &mut moves);
+        let mv = Move { from, to, promote };
+        (moves.contains(&mv) && self.try_make_move(mv).is_some()).then_some(mv)
+    }
+
+    fn san(&self, mv: Move) -> String {
+        let Cell::Piece(_, kind) = self.cells[mv.from] else {
+            panic!("no piece to move");
+        };
+        let mut result = String::new();
+        if self.is_castling(mv) {
+            result += if mv.to > mv.from { "O-O" } else { "O-O-O" };
+        } else if kind == Kind::Pawn {
+            if self.is_capture(mv) {
+                result += &square_name(mv.from)[..1];
+                result.push('x');
+            }
+            result += &square_name(mv.to);
+            if let Some(promote) = mv.promote {
+                result.push('=');
+                result.push(promote.as_char().to_ascii_uppercase());
+            }
+        } else {
+            result.push(kind.as_char().to_ascii_uppercase());
+            let others: Vec<_> = self
+                .pseudo_legal_moves()
+                .into_iter()
+                .filter(|m| m.to == mv.to && m.from != mv.from)
+                .filter(|m| self.cells[m.from] == self.cells[mv.from])
+                .filter(|&m| self.try_make_move(m).is_some())
+                .map(|m| m.from)
+                .collect();
+            let same_file = others.iter().any(|&sq| file_of(sq) == file_of(mv.from));
+            let same_rank = others.iter().any(|&sq| rank_of(sq) == rank_of(mv.from));
+            let name = square_name(mv.from);
+            if !others.is_empty() && (!same_file || same_rank) {
+                result += &name[..1];
+            }
+            if same_file {
+                result += &name[1..];
+            }
+            if self.is_capture(mv) {
+                result.push('x');
+            }
+            result += &square_name(mv.to);
+        }
+
+        let after = self.try_make_move(mv).expect("move is not legal");
+        if after.is_check() {
+            result.push(match after.has_legal_moves() {
+                true => '+',
+                false => '#',
+            });
+        }
+        result
+    }
+
+    fn is_insufficient_material(&self) -> bool {
+        let mut minors = 0;
+        let mut knights = 0;
+        let mut bishop_colors = [false; 2];
+        for sq in all_squares() {
+            match self.cells[sq] {
+                Cell::Piece(_, Kind::Pawn | Kind::Rook | Kind::Queen) => return false,
+                Cell::Piece(_, Kind::Knight) => {
+                    minors += 1;
+                    knights += 1;
+                }
+                Cell::Piece(_, Kind::Bishop) => {
+                    minors += 1;
+                    bishop_colors[(file_of(sq) + rank_of(sq)) % 2] = true;
+                }
+                _ => {}
+            }
+        }
+        // Either a single minor piece, or only bishops on the squares of the same color.
+        minors <= 1 || (knights == 0 && !(bishop_colors[0] && bishop_colors[1]))
+    }
+}
+
+impl crate::Test for Test {
+    type Board = Board;
+    type Move = Move;
+    type Undo = Board;
+
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        Board::from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        let new_board = board.try_make_move(*mv).ok_or(MoveNotLegal)?;
+        Ok(std::mem::replace(board, new_board))
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, _mv: &Self::Move, u: &Self::Undo) {
+        *board = *u;
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        mv.uci()
+    }
+
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
+        b.pseudo_legal_moves().into_iter().for_each(visit);
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        let color = if is_white { Color::White } else { Color::Black };
+        let sq = square(cx as usize - 'a' as usize, cy as usize - '1' as usize);
+        b.is_attacked(sq, color)
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        b.is_check()
+    }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        Some(b.normalized_fen())
+    }
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.is_check() && !b.has_legal_moves())
+    }
+
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(!b.is_check() && !b.has_legal_moves())
+    }
+
+    fn is_insufficient_material(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.is_insufficient_material())
+    }
+
+    fn is_uci_move_legal(&self, b: &Self::Board, s: &str) -> Option<bool> {
+        Some(b.parse_uci(s).is_some())
+    }
+
+    fn move_to_san(&self, b: &Self::Board, mv: &Self::Move) -> Option<String> {
+        Some(b.san(*mv))
+    }
+
+    fn move_from_san(&self, b: &Self::Board, s: &str) -> Option<Result<Self::Move, MoveNotLegal>> {
+        // Check and mate marks are optional in the input.
+        let s = s.trim_end_matches(['+', '#']);
+        // Converting all the legal moves into SAN is slow, so we skip the ones which obviously
+        // don't match.
+        let found = b
+            .legal_moves()
+            .into_iter()
+            .filter(|&mv| b.is_castling(mv) || s.contains(&square_name(mv.to)))
+            .find(|&mv| b.san(mv).trim_end_matches(['+', '#']) == s);
+        Some(found.ok_or(MoveNotLegal))
+    }
+}
+
+impl Perft {
+    fn hperft_v1_leaf(board: &Board) -> u64 {
+        let mut white = 0;
+        let mut black = 0;
+        for sq in all_squares() {
+            match board.cells[sq] {
+                Cell::Piece(Color::White, _) => white |= square_bit(sq),
+                Cell::Piece(Color::Black, _) => black |= square_bit(sq),
+                _ => {}
+            }
+        }
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(board: &Board) -> u64 {
+        let mut pos = hperft::Position {
+            white: 0,
+            black: 0,
+            pieces: [0; 6],
+            white_to_move: board.side == Color::White,
+            castling: board.castling,
+            ep_file: board.ep.map(file_of),
+        };
+        for sq in all_squares() {
+            if let Cell::Piece(color, kind) = board.cells[sq] {
+                match color {
+                    Color::White => pos.white |= square_bit(sq),
+                    Color::Black => pos.black |= square_bit(sq),
+                }
+                pos.pieces[kind as usize] |= square_bit(sq);
+            }
+        }
+        pos.hash()
+    }
+
+    fn do_perft(board: &Board, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut count = 0;
+        for mv in board.pseudo_legal_moves() {
+            if let Some(child) = board.try_make_move(mv) {
+                count += Self::do_perft(&child, depth - 1);
+            }
+        }
+        count
+    }
+
+    fn do_hperft(board: &Board, depth: usize, leaf: impl Fn(&Board) -> u64 + Copy) -> u64 {
+        if depth == 0 {
+            return leaf(board);
+        }
+        let mut count: u64 = 0;
+        for mv in board.pseudo_legal_moves() {
+            if let Some(child) = board.try_make_move(mv) {
+                count = count.wrapping_add(Self::do_hperft(&child, depth - 1, leaf));
+            }
+        }
+        count
+    }
+
+    fn do_hashed_perft(board: &Board, depth: usize, table: &mut PerftTable) -> u64 {
+        if depth <= 1 {
+            return Self::do_perft(board, depth);
+        }
+        if let Some(count) = table.get(board.hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        for mv in board.pseudo_legal_moves() {
+            if let Some(child) = board.try_make_move(mv) {
+                count += Self::do_hashed_perft(&child, depth - 1, table);
+            }
+        }
+        table.insert(board.hash, depth, count);
+        count
+    }
+
+    fn do_verify_hash(
+        board: &Board,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = board.fen();
+        let rebuilt = Board::from_fen(&fen)?.hash;
+        if let Some(m) = HashMismatch::check(NAME, path, fen, board.hash, rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        for mv in board.legal_moves() {
+            path.push(mv.uci());
+            let res = Self::do_verify_hash(&board.try_make_move(mv).unwrap(), depth - 1, path);
+            if !matches!(res, Ok(None)) {
+                return res;
+            }
+            path.pop();
+        }
+        Ok(None)
+    }
+
+    fn do_perft_stats(board: &Board, depth: usize, stats: &mut PerftStats) {
+        for mv in board.legal_moves() {
+            let child = board.try_make_move(mv).unwrap();
+            if depth > 1 {
+                Self::do_perft_stats(&child, depth - 1, stats);
+                continue;
+            }
+            let castle = board.is_castling(mv);
+            stats.add_leaf(Leaf {
+                capture: board.is_capture(mv),
+                en_passant: board.is_en_passant(mv),
+                castle,
+                promotion: mv.promote.is_some(),
+                checkers: child.checkers(),
+                moved: if castle { !0 } else { square_bit(mv.to) },
+                has_moves: child.has_legal_moves(),
+            });
+        }
+    }
+}
+
+impl crate::Perft for Perft {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = Board::from_fen(fen)?;
+        Ok(Self::do_perft(&board, depth))
+    }
+
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = Board::from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v1_leaf))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = Board::from_fen(fen)?;
+        Ok(Self::do_hperft(&board, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let board = Board::from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        for mv in board.legal_moves() {
+            let child = board.try_make_move(mv).unwrap();
+            result.insert(mv.uci(), Self::do_perft(&child, depth - 1));
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let board = Board::from_fen(fen)?;
+        let parsed = board
+            .parse_uci(mv)
+            .ok_or_else(|| Error::illegal_move(NAME, fen, mv))?;
+        Ok(board.try_make_move(parsed).unwrap().fen())
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let board = Board::from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&board, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let board = Board::from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&board, depth, &mut stats),
+        }
+        Ok(stats)
+    }
+
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let board = Board::from_fen(fen)?;
+        Self::do_verify_hash(&board, depth, &mut Vec::new())
+    }
+}
@@ -1,15 +1,207 @@
 pub mod chess;
 pub mod cozy_chess;
+pub mod mailbox;
 pub mod owlchess;
 pub mod pleco;
 pub mod shakmaty;
 
-pub fn all_perft() -> Vec<Box<dyn super::Perft>> {
+use crate::{selftest::DynTester, Perft};
+
+// Optional selftest features which are fully supported by the implementation and agree with the
+// other implementations, so they are checked by the tests. Chess960 support is reported by
+// `Perft::supports_chess960()` instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub fen_export: bool,
+    pub terminal_status: bool,
+    pub uci_moves: bool,
+    pub san: bool,
+}
+
+pub struct Implementation {
+    pub name: &'static str,
+    pub perft: Box<dyn Perft>,
+    pub selftest: Option<Box<dyn DynTester>>,
+    pub caps: Capabilities,
+    // Slow implementations which serve only as a reference for the others. They are not
+    // benchmarked, and the consensus tool skips them unless asked explicitly.
+    pub reference_only: bool,
+}
+
+// All the implementations. The first one is used as a reference in some tests.
+pub fn registry() -> Vec<Implementation> {
     vec![
-        Box::new(chess::Perft),
-        Box::new(owlchess::Perft),
-        Box::new(shakmaty::Perft),
-        Box::new(pleco::Perft),
-        Box::new(cozy_chess::Perft),
+        Implementation {
+            name: "mailbox",
+            perft: Box::new(mailbox::Perft),
+            selftest: Some(Box::new(mailbox::Test)),
+            caps: Capabilities {
+                fen_export: true,
+                terminal_status: true,
+                uci_moves: true,
+                san: true,
+            },
+            reference_only: true,
+        },
+        // `chess` cannot export FEN and detect insufficient material. Also, it cannot parse
+        // promotions like `e8=Q` and en passant captures in SAN.
+        Implementation {
+            name: "chess",
+            perft: Box::new(chess::Perft),
+            selftest: Some(Box::new(chess::Test)),
+            caps: Capabilities {
+                uci_moves: true,
+                ..Default::default()
+            },
+            reference_only: false,
+        },
+        Implementation {
+            name: "owlchess",
+            perft: Box::new(owlchess::Perft),
+            selftest: Some(Box::new(owlchess::Test)),
+            caps: Capabilities {
+                fen_export: true,
+                terminal_status: true,
+                uci_moves: true,
+                san: true,
+            },
+            reference_only: false,
+        },
+        // `shakmaty` sometimes writes both file and rank of the moving piece into SAN when rank
+        // alone is enough (e.g. `Qb2b4` instead of `Q2b4`).
+        Implementation {
+            name: "shakmaty",
+            perft: Box::new(shakmaty::Perft),
+            selftest: Some(Box::new(shakmaty::Test)),
+            caps: Capabilities {
+                fen_export: true,
+                terminal_status: true,
+                uci_moves: true,
+                ..Default::default()
+            },
+            reference_only: false,
+        },
+        // `pleco` cannot detect insufficient material, parse UCI moves by itself and use SAN.
+        Implementation {
+            name: "pleco",
+            perft: Box::new(pleco::Perft),
+            selftest: Some(Box::new(pleco::Test)),
+            caps: Capabilities {
+                fen_export: true,
+                ..Default::default()
+            },
+            reference_only: false,
+        },
+        // `cozy_chess` cannot detect insufficient material.
+        Implementation {
+            name: "cozy_chess",
+            perft: Box::new(cozy_chess::Perft),
+            selftest: Some(Box::new(cozy_chess::Test)),
+            caps: Capabilities {
+                fen_export: true,
+                uci_moves: true,
+                san: true,
+                ..Default::default()
+            },
+            reference_only: false,
+        },
     ]
 }
+
+pub fn find(name: &str) -> Option<Implementation> {
+    registry().into_iter().find(|i| i.name == name)
+}
+
+pub fn all_perft() -> Vec<Box<dyn Perft>> {
+    registry().into_iter().map(|i| i.perft).collect()
+}
+
+pub fn find_perft(name: &str) -> Option<Box<dyn Perft>> {
+    find(name).map(|i| i.perft)
+}
+
+// Replaces the en passant square in `fen` with "-". Used by the implementations which write the en
+// passant square into FEN even if en passant capture is not legal.
+pub(crate) fn fen_without_ep(fen: &str) -> String {
+    let mut parts: Vec<_> = fen.split(' ').collect();
+    parts[3] = "-";
+    parts.join(" ")
+}
+
+// Converts X-FEN castling rights (`KQkq`, which denote the outermost rooks) into Shredder-FEN, which
+// always uses rook files. Shredder-FEN castling rights are kept as is.
+pub(crate) fn shredder_fen(fen: &str) -> String {
+    let mut parts: Vec<_> = fen.split(' ').map(str::to_string).collect();
+    if parts.len() < 3 {
+        return fen.to_string();
+    }
+    let ranks: Vec<Vec<char>> = parts[0]
+        .split('/')
+        .map(|rank| {
+            rank.chars()
+                .flat_map(|c| match c.to_digit(10) {
+                    Some(n) => vec!['.'; n as usize],
+                    None => vec![c],
+                })
+                .collect()
+        })
+        .collect();
+    let castling = parts[2]
+        .chars()
+        .map(|c| {
+            let (rank, king, rook) = match c {
+                'K' | 'Q' => (ranks.last(), 'K', 'R'),
+                'k' | 'q' => (ranks.first(), 'k', 'r'),
+                _ => return c,
+            };
+            let Some(rank) = rank else { return c };
+            let Some(king_file) = rank.iter().position(|&p| p == king) else {
+                return c;
+            };
+            let rook_file = match c {
+                'K' | 'k' => rank
+                    .iter()
+                    .rposition(|&p| p == rook)
+                    .filter(|&f| f > king_file),
+                _ => rank
+                    .iter()
+                    .position(|&p| p == rook)
+                    .filter(|&f| f < king_file),
+            };
+            match rook_file {
+                Some(f) if king == 'K' => (b'A' + f as u8) as char,
+                Some(f) => (b'a' + f as u8) as char,
+                None => c,
+            }
+        })
+        .collect();
+    parts[2] = castling;
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shredder_fen() {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(
+            shredder_fen(fen),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+        );
+        let fen = "1r1k1r1r/8/8/8/8/8/8/RR2K1R1 b Qk - 0 1";
+        assert_eq!(shredder_fen(fen), "1r1k1r1r/8/8/8/8/8/8/RR2K1R1 b Ah - 0 1");
+        let fen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        assert_eq!(shredder_fen(fen), fen);
+    }
+
+    #[test]
+    fn test_registry() {
+        let impls = registry();
+        for (i, imp) in impls.iter().enumerate() {
+            assert_eq!(imp.perft.name(), imp.name);
+            assert!(impls[..i].iter().all(|other| other.name != imp.name));
+        }
+    }
+}
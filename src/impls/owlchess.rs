@@ -1,13 +1,28 @@
-use crate::MoveNotLegal;
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
 use owlchess::{
     movegen::{self, legal, semilegal},
-    moves::{self, make::TryUnchecked, RawUndo},
-    selftest, Board, Color, Coord, File, Make, Move, MoveList, Rank, RawBoard,
+    moves::{self, make::TryUnchecked, MoveKind, RawUndo},
+    selftest, Bitboard, Board, CastlingSide, Color, Coord, DrawReason, File, Make, Move, Outcome,
+    Piece, Rank, RawBoard, WinReason,
 };
+use std::collections::BTreeMap;
 
+#[derive(Clone, Copy)]
 pub struct Test;
 pub struct Perft;
 
+const NAME: &str = "owlchess";
+
+fn board_from_fen(fen: &str) -> Result<Board, Error> {
+    Board::from_fen(fen).map_err(|e| Error::invalid_fen(NAME, fen, e))
+}
+
 pub struct Undo {
     cur: RawBoard,
     undo: RawUndo,
@@ -16,19 +31,14 @@ pub struct Undo {
 impl crate::Test for Test {
     type Board = Board;
     type Move = Move;
-    type MoveList = MoveList;
     type Undo = Undo;
 
-    fn get_move<'a>(&self, list: &'a Self::MoveList, idx: usize) -> &'a Self::Move {
-        &list[idx]
-    }
-
-    fn move_count(&self, list: &Self::MoveList) -> usize {
-        list.len()
+    fn name(&self) -> &'static str {
+        NAME
     }
 
-    fn board_from_fen(&self, fen: &str) -> Self::Board {
-        Board::from_fen(fen).expect("invalid fen")
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        board_from_fen(fen)
     }
 
     fn try_make_move(
@@ -57,9 +67,9 @@ impl crate::Test for Test {
         mv.to_string()
     }
 
-    fn generate_moves(&self, b: &Self::Board) -> Self::MoveList {
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
         assert!(!b.is_opponent_king_attacked());
-        semilegal::gen_all(b)
+        semilegal::gen_all(b).iter().copied().for_each(visit);
     }
 
     fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
@@ -75,6 +85,54 @@ impl crate::Test for Test {
     fn run_self_test(&self, b: &Self::Board) {
         selftest::selftest(b)
     }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        let fen = b.as_fen();
+        if b.raw().ep_source.is_none() {
+            return Some(fen);
+        }
+        // We don't use `legal` module here, as it may consider en passant legal when the capturing
+        // pawn is pinned.
+        let has_ep = semilegal::gen_all(b).iter().any(|mv| {
+            let mut b = b.clone();
+            mv.kind() == MoveKind::Enpassant
+                && unsafe { TryUnchecked::new(*mv) }.make_raw(&mut b).is_ok()
+        });
+        match has_ep {
+            true => Some(fen),
+            false => Some(super::fen_without_ep(&fen)),
+        }
+    }
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(matches!(
+            b.calc_outcome(),
+            Some(Outcome::Win {
+                reason: WinReason::Checkmate,
+                ..
+            })
+        ))
+    }
+
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.calc_outcome() == Some(Outcome::Draw(DrawReason::Stalemate)))
+    }
+
+    fn is_insufficient_material(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.calc_draw_simple() == Some(DrawReason::InsufficientMaterial))
+    }
+
+    fn is_uci_move_legal(&self, b: &Self::Board, s: &str) -> Option<bool> {
+        Some(Move::from_uci_legal(s, b).is_ok())
+    }
+
+    fn move_to_san(&self, b: &Self::Board, mv: &Self::Move) -> Option<String> {
+        Some(mv.san(b).expect("move is not legal").to_string())
+    }
+
+    fn move_from_san(&self, b: &Self::Board, s: &str) -> Option<Result<Self::Move, MoveNotLegal>> {
+        Some(Move::from_san(s, b).map_err(|_| MoveNotLegal))
+    }
 }
 
 impl Perft {
@@ -97,13 +155,47 @@ impl Perft {
         }
     }
 
-    fn do_hperft(b: &mut Board, depth: usize) -> u64 {
+    fn hperft_v1_leaf(b: &Board) -> u64 {
+        let white: u64 = b.color(Color::White).flipped_rank().into();
+        let black: u64 = b.color(Color::Black).flipped_rank().into();
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(b: &Board) -> u64 {
+        let pieces = [
+            Piece::Pawn,
+            Piece::Knight,
+            Piece::Bishop,
+            Piece::Rook,
+            Piece::Queen,
+            Piece::King,
+        ];
+        let castling = b.raw().castling;
+        hperft::Position {
+            white: b.color(Color::White).flipped_rank().into(),
+            black: b.color(Color::Black).flipped_rank().into(),
+            pieces: pieces.map(|p| {
+                (b.piece2(Color::White, p) | b.piece2(Color::Black, p))
+                    .flipped_rank()
+                    .into()
+            }),
+            white_to_move: b.side() == Color::White,
+            castling: [
+                castling.has(Color::White, CastlingSide::King),
+                castling.has(Color::White, CastlingSide::Queen),
+                castling.has(Color::Black, CastlingSide::King),
+                castling.has(Color::Black, CastlingSide::Queen),
+            ],
+            ep_file: b.raw().ep_source.map(|c| c.file().index()),
+        }
+        .hash()
+    }
+
+    fn do_hperft(b: &mut Board, depth: usize, leaf: impl Fn(&Board) -> u64 + Copy) -> u64 {
         if depth == 0 {
-            let white: u64 = b.color(Color::White).flipped_rank().into();
-            let black: u64 = b.color(Color::Black).flipped_rank().into();
-            return white
-                .wrapping_mul(crate::HPERFT_WHITE)
-                .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK));
+            return leaf(b);
         }
 
         let mut result: u64 = 0;
@@ -113,25 +205,173 @@ impl Perft {
                 Ok((_, u)) => u,
                 Err(_) => continue,
             };
-            result = result.wrapping_add(Self::do_hperft(b, depth - 1));
+            result = result.wrapping_add(Self::do_hperft(b, depth - 1, leaf));
             unsafe { moves::unmake_move_unchecked(b, *mv, u) };
         }
         result
     }
+
+    fn do_hashed_perft(b: &mut Board, depth: usize, table: &mut PerftTable) -> u64 {
+        match depth {
+            0 => return 1,
+            1 => return Self::do_perft(b, 1),
+            _ => {}
+        }
+
+        let hash = b.zobrist_hash();
+        if let Some(count) = table.get(hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        for mv in &semilegal::gen_all(b) {
+            let u = match unsafe { TryUnchecked::new(*mv) }.make_raw(b) {
+                Ok((_, u)) => u,
+                Err(_) => continue,
+            };
+            count += Self::do_hashed_perft(b, depth - 1, table);
+            unsafe { moves::unmake_move_unchecked(b, *mv, u) };
+        }
+        table.insert(hash, depth, count);
+        count
+    }
+
+    fn do_verify_hash(
+        b: &mut Board,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = b.as_fen();
+        let rebuilt = board_from_fen(&fen)?.zobrist_hash();
+        if let Some(m) = HashMismatch::check(NAME, path, fen, b.zobrist_hash(), rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        for mv in &semilegal::gen_all(b) {
+            let u = match unsafe { TryUnchecked::new(*mv) }.make_raw(b) {
+                Ok((_, u)) => u,
+                Err(_) => continue,
+            };
+            path.push(mv.to_string());
+            let res = Self::do_verify_hash(b, depth - 1, path);
+            unsafe { moves::unmake_move_unchecked(b, *mv, u) };
+            if !matches!(res, Ok(None)) {
+                return res;
+            }
+            path.pop();
+        }
+        Ok(None)
+    }
+
+    fn has_legal_moves(b: &mut Board) -> bool {
+        semilegal::gen_all(b)
+            .iter()
+            .any(|mv| match unsafe { TryUnchecked::new(*mv) }.make_raw(b) {
+                Ok((_, u)) => {
+                    unsafe { moves::unmake_move_unchecked(b, *mv, u) };
+                    true
+                }
+                Err(_) => false,
+            })
+    }
+
+    fn do_perft_stats(b: &mut Board, depth: usize, stats: &mut PerftStats) {
+        for mv in &semilegal::gen_all(b) {
+            let capture = b.get(mv.dst()).is_occupied() || mv.kind() == MoveKind::Enpassant;
+            let u = match unsafe { TryUnchecked::new(*mv) }.make_raw(b) {
+                Ok((_, u)) => u,
+                Err(_) => continue,
+            };
+            if depth > 1 {
+                Self::do_perft_stats(b, depth - 1, stats);
+            } else {
+                let castle = matches!(
+                    mv.kind(),
+                    MoveKind::CastlingKingside | MoveKind::CastlingQueenside
+                );
+                stats.add_leaf(Leaf {
+                    capture,
+                    en_passant: mv.kind() == MoveKind::Enpassant,
+                    castle,
+                    promotion: mv.kind().promote().is_some(),
+                    checkers: b.checkers().as_raw(),
+                    moved: if castle {
+                        !0
+                    } else {
+                        Bitboard::from_coord(mv.dst()).as_raw()
+                    },
+                    has_moves: Self::has_legal_moves(b),
+                });
+            }
+            unsafe { moves::unmake_move_unchecked(b, *mv, u) };
+        }
+    }
 }
 
 impl crate::Perft for Perft {
     fn name(&self) -> &'static str {
-        "owlchess"
+        NAME
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_perft(&mut board, depth))
     }
 
-    fn perft(&self, fen: &str, depth: usize) -> u64 {
-        let mut board = Board::from_fen(fen).expect("invalid fen");
-        Self::do_perft(&mut board, depth)
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&mut board, depth, Self::hperft_v1_leaf))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&mut board, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let mut board = board_from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        for mv in &semilegal::gen_all(&board) {
+            let u = match unsafe { TryUnchecked::new(*mv) }.make_raw(&mut board) {
+                Ok((_, u)) => u,
+                Err(_) => continue,
+            };
+            result.insert(mv.to_string(), Self::do_perft(&mut board, depth - 1));
+            unsafe { moves::unmake_move_unchecked(&mut board, *mv, u) };
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let board = board_from_fen(fen)?;
+        Move::from_uci_legal(mv, &board)
+            .ok()
+            .and_then(|m| board.make_move(m).ok())
+            .map(|b| b.as_fen())
+            .ok_or_else(|| Error::illegal_move(NAME, fen, mv))
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&mut board, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let mut board = board_from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&mut board, depth, &mut stats),
+        }
+        Ok(stats)
     }
 
-    fn hperft(&self, fen: &str, depth: usize) -> u64 {
-        let mut board = Board::from_fen(fen).expect("invalid fen");
-        Self::do_hperft(&mut board, depth)
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let mut board = board_from_fen(fen)?;
+        Self::do_verify_hash(&mut board, depth, &mut Vec::new())
     }
 }
@@ -1,41 +1,309 @@
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
 use pleco::board::{perft, Board};
-use pleco::{MoveList, Player};
+use pleco::{
+    core::{CastleType, PieceType},
+    BitMove, MoveList, Player, SQ,
+};
+use std::collections::BTreeMap;
 
 pub struct Perft;
+#[derive(Clone, Copy)]
+pub struct Test;
+
+const NAME: &str = "pleco";
+
+fn board_from_fen(fen: &str) -> Result<Board, Error> {
+    // `FenBuildError` doesn't implement `Display`, and its `Debug` output ends with a newline.
+    Board::from_fen(fen).map_err(|e| Error::invalid_fen(NAME, fen, format!("{:?}", e).trim_end()))
+}
+
+// Pleco writes the en passant square whenever the pawn can be captured by a pseudo-legal move, so
+// we remove it if en passant capture is not legal.
+fn normalized_fen(board: &Board) -> String {
+    let fen = board.fen();
+    match board.ep_square() == SQ::NO_SQ || board.generate_moves().iter().any(|m| m.is_en_passant())
+    {
+        true => fen,
+        false => super::fen_without_ep(&fen),
+    }
+}
+
+impl crate::Test for Test {
+    type Board = Board;
+    type Move = BitMove;
+    type Undo = ();
+
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        board_from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        board.apply_move(*mv);
+        Ok(())
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, _mv: &Self::Move, _u: &Self::Undo) {
+        board.undo_move();
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        mv.stringify()
+    }
+
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
+        b.generate_moves().into_iter().for_each(visit);
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        let player = if is_white {
+            Player::White
+        } else {
+            Player::Black
+        };
+        let sq = SQ((cy as u8 - b'1') * 8 + (cx as u8 - b'a'));
+        (b.attackers_to(sq, b.occupied()) & b.get_occupied_player(player)).is_not_empty()
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        b.in_check()
+    }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        Some(normalized_fen(b))
+    }
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.checkmate())
+    }
+
+    // `Board::stalemate()` is not used, as it also returns `true` if the halfmove clock is at least
+    // 50.
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(!b.in_check() && b.generate_moves().is_empty())
+    }
+
+    // `is_uci_move_legal()` is not supported, as pleco parses UCI moves only by comparing them with
+    // the string representations of all the legal moves.
+}
+
+// Pleco initializes the Zobrist keys for pieces in the range `WhitePawn..BlackKing`, which excludes
+// `BlackKing`, so all its keys are zero, and the native hash doesn't depend on the black king
+// location. So we also mix in the occupancy of both sides.
+fn table_hash(board: &Board) -> u64 {
+    board.zobrist()
+        ^ board.occupied_white().0.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ board.occupied_black().0.wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+}
 
 impl Perft {
-    fn do_hperft(board: &mut Board, depth: usize) -> u64 {
+    fn hperft_v1_leaf(board: &Board) -> u64 {
+        let white = board.get_occupied_player(Player::White).0;
+        let black = board.get_occupied_player(Player::Black).0;
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(board: &Board) -> u64 {
+        let pieces = [
+            PieceType::P,
+            PieceType::N,
+            PieceType::B,
+            PieceType::R,
+            PieceType::Q,
+            PieceType::K,
+        ];
+        let castle = |p, c| board.can_castle(p, c);
+        hperft::Position {
+            white: board.get_occupied_player(Player::White).0,
+            black: board.get_occupied_player(Player::Black).0,
+            pieces: pieces.map(|p| board.piece_bb_both_players(p).0),
+            white_to_move: board.turn() == Player::White,
+            castling: [
+                castle(Player::White, CastleType::KingSide),
+                castle(Player::White, CastleType::QueenSide),
+                castle(Player::Black, CastleType::KingSide),
+                castle(Player::Black, CastleType::QueenSide),
+            ],
+            ep_file: match board.ep_square() {
+                SQ::NO_SQ => None,
+                sq => Some(sq.file() as usize),
+            },
+        }
+        .hash()
+    }
+
+    fn do_hperft(board: &mut Board, depth: usize, leaf: impl Fn(&Board) -> u64 + Copy) -> u64 {
         if depth == 0 {
-            let white = board.get_occupied_player(Player::White).0;
-            let black = board.get_occupied_player(Player::Black).0;
-            return white
-                .wrapping_mul(crate::HPERFT_WHITE)
-                .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK));
+            return leaf(board);
         }
 
         let moves: MoveList = board.generate_moves();
         let mut count: u64 = 0;
         for mov in moves {
             board.apply_move(mov);
-            count = count.wrapping_add(Self::do_hperft(board, depth - 1));
+            count = count.wrapping_add(Self::do_hperft(board, depth - 1, leaf));
+            board.undo_move();
+        }
+        count
+    }
+
+    fn do_hashed_perft(board: &mut Board, depth: usize, table: &mut PerftTable) -> u64 {
+        if depth <= 1 {
+            return perft::perft(board, depth as u16);
+        }
+
+        let hash = table_hash(board);
+        if let Some(count) = table.get(hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        for mov in board.generate_moves() {
+            board.apply_move(mov);
+            count += Self::do_hashed_perft(board, depth - 1, table);
             board.undo_move();
         }
+        table.insert(hash, depth, count);
         count
     }
+
+    fn do_verify_hash(
+        board: &mut Board,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = board.fen();
+        let rebuilt = board_from_fen(&fen)?.zobrist();
+        if let Some(m) = HashMismatch::check(NAME, path, fen, board.zobrist(), rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        for mov in board.generate_moves() {
+            board.apply_move(mov);
+            path.push(mov.stringify());
+            let res = Self::do_verify_hash(board, depth - 1, path);
+            board.undo_move();
+            if !matches!(res, Ok(None)) {
+                return res;
+            }
+            path.pop();
+        }
+        Ok(None)
+    }
+
+    fn do_perft_stats(board: &mut Board, depth: usize, stats: &mut PerftStats) {
+        for mov in board.generate_moves() {
+            board.apply_move(mov);
+            if depth > 1 {
+                Self::do_perft_stats(board, depth - 1, stats);
+            } else {
+                stats.add_leaf(Leaf {
+                    capture: mov.is_capture(),
+                    en_passant: mov.is_en_passant(),
+                    castle: mov.is_castle(),
+                    promotion: mov.is_promo(),
+                    checkers: board.checkers().0,
+                    moved: if mov.is_castle() {
+                        !0
+                    } else {
+                        mov.get_dest().to_bb().0
+                    },
+                    has_moves: !board.generate_moves().is_empty(),
+                });
+            }
+            board.undo_move();
+        }
+    }
 }
 
 impl crate::Perft for Perft {
     fn name(&self) -> &'static str {
-        "pleco"
+        NAME
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let board = board_from_fen(fen)?;
+        Ok(perft::perft(&board, depth as u16))
     }
 
-    fn perft(&self, fen: &str, depth: usize) -> u64 {
-        let board = Board::from_fen(fen).expect("invalid fen");
-        perft::perft(&board, depth as u16)
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&mut board, depth, Self::hperft_v1_leaf))
     }
 
-    fn hperft(&self, fen: &str, depth: usize) -> u64 {
-        let mut board = Board::from_fen(fen).expect("invalid fen");
-        Self::do_hperft(&mut board, depth)
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hperft(&mut board, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let mut board = board_from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        for mov in board.generate_moves() {
+            board.apply_move(mov);
+            result.insert(mov.stringify(), perft::perft(&board, depth as u16 - 1));
+            board.undo_move();
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let mut board = board_from_fen(fen)?;
+        if !board.apply_uci_move(mv) {
+            return Err(Error::illegal_move(NAME, fen, mv));
+        }
+        Ok(board.fen())
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let mut board = board_from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&mut board, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let mut board = board_from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&mut board, depth, &mut stats),
+        }
+        Ok(stats)
+    }
+
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let mut board = board_from_fen(fen)?;
+        Self::do_verify_hash(&mut board, depth, &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_black_king_hash() {
+        let first = board_from_fen("8/8/5k2/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let second = board_from_fen("8/8/6k1/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(first.zobrist(), second.zobrist());
+        assert_ne!(table_hash(&first), table_hash(&second));
     }
 }
@@ -1,48 +1,315 @@
-use shakmaty::{fen::Fen, CastlingMode, Chess, Color, Position};
+use crate::{
+    hperft,
+    perft::{Leaf, PerftStats},
+    tt::PerftTable,
+    zobrist::HashMismatch,
+    Error, MoveNotLegal,
+};
+use shakmaty::{
+    fen::Fen,
+    san::SanPlus,
+    uci::Uci,
+    zobrist::{Zobrist64, ZobristHash},
+    Bitboard, CastlingMode, CastlingSide, Chess, Color, EnPassantMode, File, Move, Position, Rank,
+    Role, Square,
+};
+use std::collections::BTreeMap;
 
 pub struct Perft;
+#[derive(Clone, Copy)]
+pub struct Test;
+
+const NAME: &str = "shakmaty";
+
+fn position_from_fen_with_mode(fen: &str, mode: CastlingMode) -> Result<Chess, Error> {
+    fen.parse::<Fen>()
+        .map_err(|e| Error::invalid_fen(NAME, fen, e))?
+        .into_position(mode)
+        .map_err(|e| Error::invalid_setup(NAME, fen, e))
+}
+
+fn position_from_fen(fen: &str) -> Result<Chess, Error> {
+    position_from_fen_with_mode(fen, CastlingMode::Standard)
+}
+
+impl crate::Test for Test {
+    type Board = Chess;
+    type Move = Move;
+    type Undo = Chess;
+
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        position_from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        let old = board.clone();
+        board.play_unchecked(mv);
+        Ok(old)
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, _mv: &Self::Move, u: &Self::Undo) {
+        *board = u.clone();
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        Uci::from_standard(mv).to_string()
+    }
+
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
+        b.legal_moves().into_iter().for_each(visit);
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        let color = if is_white { Color::White } else { Color::Black };
+        let sq = Square::from_coords(
+            File::new(cx as u32 - 'a' as u32),
+            Rank::new(cy as u32 - '1' as u32),
+        );
+        b.board().attacks_to(sq, color, b.board().occupied()).any()
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        b.is_check()
+    }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        Some(Fen::from_position(b.clone(), EnPassantMode::Legal).to_string())
+    }
+
+    fn is_checkmate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.is_checkmate())
+    }
+
+    fn is_stalemate(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.is_stalemate())
+    }
+
+    fn is_insufficient_material(&self, b: &Self::Board) -> Option<bool> {
+        Some(b.is_insufficient_material())
+    }
+
+    // `Uci::to_move()` also accepts castling written as "king captures rook" (e.g. `e1h1`) and
+    // ignores the promotion piece on non-promotion moves (e.g. `e5d6q` for en passant), so we
+    // require the move to be written back in the same way.
+    fn is_uci_move_legal(&self, b: &Self::Board, s: &str) -> Option<bool> {
+        let mv = s.parse::<Uci>().ok().and_then(|uci| uci.to_move(b).ok());
+        Some(mv.is_some_and(|mv| self.move_str(&mv) == s))
+    }
+
+    fn move_to_san(&self, b: &Self::Board, mv: &Self::Move) -> Option<String> {
+        Some(SanPlus::from_move(b.clone(), mv).to_string())
+    }
+
+    fn move_from_san(&self, b: &Self::Board, s: &str) -> Option<Result<Self::Move, MoveNotLegal>> {
+        let mv = s
+            .parse::<SanPlus>()
+            .ok()
+            .and_then(|san| san.san.to_move(b).ok());
+        Some(mv.ok_or(MoveNotLegal))
+    }
+}
 
 impl Perft {
-    fn do_hperft(pos: &Chess, depth: usize) -> u64 {
+    fn hperft_v1_leaf(pos: &Chess) -> u64 {
+        let white = pos.board().by_color(Color::White).0;
+        let black = pos.board().by_color(Color::Black).0;
+        white
+            .wrapping_mul(crate::HPERFT_WHITE)
+            .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+    }
+
+    fn hperft_v2_leaf(pos: &Chess) -> u64 {
+        let castles = pos.castles();
+        hperft::Position {
+            white: pos.board().by_color(Color::White).0,
+            black: pos.board().by_color(Color::Black).0,
+            pieces: Role::ALL.map(|r| pos.board().by_role(r).0),
+            white_to_move: pos.turn() == Color::White,
+            castling: [
+                castles.has(Color::White, CastlingSide::KingSide),
+                castles.has(Color::White, CastlingSide::QueenSide),
+                castles.has(Color::Black, CastlingSide::KingSide),
+                castles.has(Color::Black, CastlingSide::QueenSide),
+            ],
+            ep_file: pos
+                .ep_square(EnPassantMode::Always)
+                .map(|sq| sq.file() as usize),
+        }
+        .hash()
+    }
+
+    fn do_hperft(pos: &Chess, depth: usize, leaf: impl Fn(&Chess) -> u64 + Copy) -> u64 {
         if depth < 1 {
-            let white = pos.board().by_color(Color::White).0;
-            let black = pos.board().by_color(Color::Black).0;
-            white
-                .wrapping_mul(crate::HPERFT_WHITE)
-                .wrapping_add(black.wrapping_mul(crate::HPERFT_BLACK))
+            leaf(pos)
         } else {
             let moves = pos.legal_moves();
             let mut result: u64 = 0;
             for m in &moves {
                 let mut child = pos.clone();
                 child.play_unchecked(m);
-                result = result.wrapping_add(Self::do_hperft(&child, depth - 1))
+                result = result.wrapping_add(Self::do_hperft(&child, depth - 1, leaf))
             }
             result
         }
     }
+
+    fn do_hashed_perft(pos: &Chess, depth: usize, table: &mut PerftTable) -> u64 {
+        if depth <= 1 {
+            return shakmaty::perft(pos, depth as u32);
+        }
+
+        // Note that shakmaty doesn't maintain the hash incrementally, so it is calculated from
+        // scratch here.
+        let hash = Self::hash(pos);
+        if let Some(count) = table.get(hash, depth) {
+            return count;
+        }
+        let mut count = 0;
+        for m in &pos.legal_moves() {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            count += Self::do_hashed_perft(&child, depth - 1, table);
+        }
+        table.insert(hash, depth, count);
+        count
+    }
+
+    fn hash(pos: &Chess) -> u64 {
+        pos.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+    }
+
+    // As shakmaty doesn't maintain the hash incrementally, this mostly checks that FEN conversion
+    // preserves the position.
+    fn do_verify_hash(
+        pos: &Chess,
+        depth: usize,
+        path: &mut Vec<String>,
+    ) -> Result<Option<HashMismatch>, Error> {
+        let fen = Fen::from_position(pos.clone(), EnPassantMode::Legal).to_string();
+        let rebuilt = Self::hash(&position_from_fen(&fen)?);
+        if let Some(m) = HashMismatch::check(NAME, path, fen, Self::hash(pos), rebuilt) {
+            return Ok(Some(m));
+        }
+        if depth == 0 {
+            return Ok(None);
+        }
+        for m in &pos.legal_moves() {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            path.push(Uci::from_standard(m).to_string());
+            if let Some(m) = Self::do_verify_hash(&child, depth - 1, path)? {
+                return Ok(Some(m));
+            }
+            path.pop();
+        }
+        Ok(None)
+    }
+
+    fn do_perft_stats(pos: &Chess, depth: usize, stats: &mut PerftStats) {
+        for m in &pos.legal_moves() {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            if depth > 1 {
+                Self::do_perft_stats(&child, depth - 1, stats);
+                continue;
+            }
+            stats.add_leaf(Leaf {
+                capture: m.is_capture(),
+                en_passant: m.is_en_passant(),
+                castle: m.is_castle(),
+                promotion: m.is_promotion(),
+                checkers: child.checkers().0,
+                moved: if m.is_castle() {
+                    !0
+                } else {
+                    Bitboard::from_square(m.to()).0
+                },
+                has_moves: !child.legal_moves().is_empty(),
+            });
+        }
+    }
 }
 
 impl crate::Perft for Perft {
     fn name(&self) -> &'static str {
-        "shakmaty"
+        NAME
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let pos = position_from_fen(fen)?;
+        Ok(shakmaty::perft(&pos, depth as u32))
+    }
+
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let pos = position_from_fen(fen)?;
+        Ok(Self::do_hperft(&pos, depth, Self::hperft_v1_leaf))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let pos = position_from_fen(fen)?;
+        Ok(Self::do_hperft(&pos, depth, Self::hperft_v2_leaf))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        let pos = position_from_fen(fen)?;
+        let mut result = BTreeMap::new();
+        if depth == 0 {
+            return Ok(result);
+        }
+        for m in &pos.legal_moves() {
+            let mut child = pos.clone();
+            child.play_unchecked(m);
+            let count = shakmaty::perft(&child, depth as u32 - 1);
+            result.insert(Uci::from_standard(m).to_string(), count);
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        let mut pos = position_from_fen(fen)?;
+        let m = mv
+            .parse::<Uci>()
+            .ok()
+            .and_then(|uci| uci.to_move(&pos).ok())
+            .ok_or_else(|| Error::illegal_move(NAME, fen, mv))?;
+        pos.play_unchecked(&m);
+        Ok(Fen::from_position(pos, EnPassantMode::Legal).to_string())
+    }
+
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        let pos = position_from_fen(fen)?;
+        Ok(Self::do_hashed_perft(&pos, depth, table))
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let pos = position_from_fen(fen)?;
+        let mut stats = PerftStats::default();
+        match depth {
+            0 => stats.nodes = 1,
+            _ => Self::do_perft_stats(&pos, depth, &mut stats),
+        }
+        Ok(stats)
+    }
+
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        let pos = position_from_fen(fen)?;
+        Self::do_verify_hash(&pos, depth, &mut Vec::new())
     }
 
-    fn perft(&self, fen: &str, depth: usize) -> u64 {
-        let pos: Chess = fen
-            .parse::<Fen>()
-            .expect("invalid fen")
-            .into_position(CastlingMode::Standard)
-            .expect("invalid setup");
-        shakmaty::perft(&pos, depth as u32)
+    fn supports_chess960(&self) -> bool {
+        true
     }
 
-    fn hperft(&self, fen: &str, depth: usize) -> u64 {
-        let pos: Chess = fen
-            .parse::<Fen>()
-            .expect("invalid fen")
-            .into_position(CastlingMode::Standard)
-            .expect("invalid setup");
-        Self::do_hperft(&pos, depth)
+    fn perft_chess960(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let pos = position_from_fen_with_mode(fen, CastlingMode::Chess960)?;
+        Ok(shakmaty::perft(&pos, depth as u32))
     }
 }
@@ -1,7 +1,191 @@
-pub trait Perft {
+use perft::PerftStats;
+use std::{collections::BTreeMap, fmt};
+use tt::PerftTable;
+use zobrist::HashMismatch;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    InvalidFen {
+        implementation: &'static str,
+        fen: String,
+        reason: String,
+    },
+    InvalidSetup {
+        implementation: &'static str,
+        fen: String,
+        reason: String,
+    },
+    IllegalMove {
+        implementation: &'static str,
+        fen: String,
+        mv: String,
+    },
+    Unsupported {
+        implementation: &'static str,
+        feature: &'static str,
+    },
+    FenRoundTrip {
+        implementation: &'static str,
+        fen: String,
+        reason: String,
+    },
+}
+
+impl Error {
+    pub(crate) fn invalid_fen(
+        implementation: &'static str,
+        fen: &str,
+        reason: impl fmt::Display,
+    ) -> Self {
+        Self::InvalidFen {
+            implementation,
+            fen: fen.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_setup(
+        implementation: &'static str,
+        fen: &str,
+        reason: impl fmt::Display,
+    ) -> Self {
+        Self::InvalidSetup {
+            implementation,
+            fen: fen.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub(crate) fn illegal_move(implementation: &'static str, fen: &str, mv: &str) -> Self {
+        Self::IllegalMove {
+            implementation,
+            fen: fen.to_string(),
+            mv: mv.to_string(),
+        }
+    }
+
+    pub(crate) fn unsupported(implementation: &'static str, feature: &'static str) -> Self {
+        Self::Unsupported {
+            implementation,
+            feature,
+        }
+    }
+
+    pub(crate) fn fen_round_trip(
+        implementation: &'static str,
+        fen: &str,
+        reason: impl fmt::Display,
+    ) -> Self {
+        Self::FenRoundTrip {
+            implementation,
+            fen: fen.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn implementation(&self) -> &'static str {
+        match self {
+            Self::InvalidFen { implementation, .. }
+            | Self::InvalidSetup { implementation, .. }
+            | Self::IllegalMove { implementation, .. }
+            | Self::Unsupported { implementation, .. }
+            | Self::FenRoundTrip { implementation, .. } => implementation,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFen {
+                implementation,
+                fen,
+                reason,
+            } => write!(f, "{}: invalid fen \"{}\": {}", implementation, fen, reason),
+            Self::InvalidSetup {
+                implementation,
+                fen,
+                reason,
+            } => write!(
+                f,
+                "{}: invalid setup \"{}\": {}",
+                implementation, fen, reason
+            ),
+            Self::IllegalMove {
+                implementation,
+                fen,
+                mv,
+            } => write!(f, "{}: illegal move {} in \"{}\"", implementation, mv, fen),
+            Self::Unsupported {
+                implementation,
+                feature,
+            } => write!(f, "{}: {} is not supported", implementation, feature),
+            Self::FenRoundTrip {
+                implementation,
+                fen,
+                reason,
+            } => write!(
+                f,
+                "{}: fen round trip failed for \"{}\": {}",
+                implementation, fen, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub trait Perft: Sync {
     fn name(&self) -> &'static str;
-    fn perft(&self, fen: &str, depth: usize) -> u64;
-    fn hperft(&self, fen: &str, depth: usize) -> u64;
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error>;
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error>;
+
+    // Same as `hperft()`, but also hashes piece types, side to move, castling rights and en passant
+    // square of the leaf positions. `hperft()` remains as v1 of the scheme.
+    fn hperft_v2(&self, _fen: &str, _depth: usize) -> Result<u64, Error> {
+        Err(Error::unsupported(self.name(), "hperft v2"))
+    }
+
+    // Returns perft results for each legal move in the position, keyed by the move in UCI
+    // notation. Castling is always written as the king's two-square move, so the results from
+    // different implementations can be compared directly.
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error>;
+
+    // Makes the legal move `mv`, given in UCI notation, and returns FEN of the resulting position.
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error>;
+
+    // Same as `perft()`, but caches the results for subtrees in `table`, using the native Zobrist
+    // hash of the implementation.
+    fn hashed_perft(
+        &self,
+        _fen: &str,
+        _depth: usize,
+        _table: &mut PerftTable,
+    ) -> Result<u64, Error> {
+        Err(Error::unsupported(self.name(), "hashed perft"))
+    }
+
+    // Same as `perft()`, but also counts captures, checks, mates, etc. among the leaf nodes.
+    fn perft_stats(&self, _fen: &str, _depth: usize) -> Result<PerftStats, Error> {
+        Err(Error::unsupported(self.name(), "perft stats"))
+    }
+
+    // Walks the game tree up to `depth` and checks that the incrementally updated Zobrist hash in
+    // each node is equal to the hash of the same position rebuilt from its FEN. Returns the first
+    // node where the hashes differ.
+    fn verify_hash(&self, _fen: &str, _depth: usize) -> Result<Option<HashMismatch>, Error> {
+        Err(Error::unsupported(self.name(), "hash verification"))
+    }
+
+    fn supports_chess960(&self) -> bool {
+        false
+    }
+
+    // Same as `perft()`, but for Chess960. Castling rights may be given both in X-FEN and in
+    // Shredder-FEN.
+    fn perft_chess960(&self, _fen: &str, _depth: usize) -> Result<u64, Error> {
+        Err(Error::unsupported(self.name(), "chess960"))
+    }
 }
 
 pub struct MoveNotLegal;
@@ -10,11 +194,9 @@ pub trait Test {
     type Board;
     type Move;
     type Undo;
-    type MoveList;
 
-    fn get_move<'a>(&self, list: &'a Self::MoveList, idx: usize) -> &'a Self::Move;
-    fn move_count(&self, list: &Self::MoveList) -> usize;
-    fn board_from_fen(&self, fen: &str) -> Self::Board;
+    fn name(&self) -> &'static str;
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error>;
     fn try_make_move(
         &self,
         board: &mut Self::Board,
@@ -22,15 +204,74 @@ pub trait Test {
     ) -> Result<Self::Undo, MoveNotLegal>;
     fn unmake_move(&self, board: &mut Self::Board, mv: &Self::Move, u: &Self::Undo);
     fn move_str(&self, mv: &Self::Move) -> String;
-    fn generate_moves(&self, b: &Self::Board) -> Self::MoveList;
+
+    // Calls `visit` for each move in the position, so callback-based generators don't need to
+    // collect the moves into a list. The moves may be pseudo-legal, as `try_make_move()` rejects
+    // the illegal ones.
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move));
+
     fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool;
     fn is_check(&self, b: &Self::Board) -> bool;
     fn run_self_test(&self, _b: &Self::Board) {}
+
+    // Exports the board into FEN, or returns `None` if the implementation cannot do this. The
+    // output must be normalized, so all the implementations agree on it: the en passant square is
+    // written only if en passant capture is legal, and castling rights are written as `KQkq`.
+    fn board_to_fen(&self, _b: &Self::Board) -> Option<String> {
+        None
+    }
+
+    // Terminal status queries, which return `None` if not supported by the implementation.
+    // Insufficient material means that no sequence of legal moves leads to checkmate, i.e. the
+    // position is dead according to FIDE rules.
+    fn is_checkmate(&self, _b: &Self::Board) -> Option<bool> {
+        None
+    }
+
+    fn is_stalemate(&self, _b: &Self::Board) -> Option<bool> {
+        None
+    }
+
+    fn is_insufficient_material(&self, _b: &Self::Board) -> Option<bool> {
+        None
+    }
+
+    // Parses the move in UCI format and checks whether it's legal, using the implementation's own
+    // routines. Returns `None` if not supported by the implementation.
+    fn is_uci_move_legal(&self, _b: &Self::Board, _s: &str) -> Option<bool> {
+        None
+    }
+
+    // Converts the legal move into SAN, or returns `None` if the implementation cannot do this.
+    fn move_to_san(&self, _b: &Self::Board, _mv: &Self::Move) -> Option<String> {
+        None
+    }
+
+    // Parses the move in SAN. Returns `Some(Err(_))` if the move is invalid or not legal, and `None`
+    // if not supported by the implementation.
+    fn move_from_san(
+        &self,
+        _b: &Self::Board,
+        _s: &str,
+    ) -> Option<Result<Self::Move, MoveNotLegal>> {
+        None
+    }
 }
 
 pub(crate) const HPERFT_WHITE: u64 = 142867;
 pub(crate) const HPERFT_BLACK: u64 = 285709;
 
+pub mod bisect;
+pub mod consensus;
+pub mod diff;
+pub mod golden;
+mod hperft;
 pub mod impls;
+pub mod parallel;
 pub mod perft;
 pub mod selftest;
+pub mod suite;
+#[cfg(test)]
+mod testutil;
+pub mod tt;
+pub mod zobrist;
@@ -0,0 +1,201 @@
+use crate::{perft::PerftStats, tt::PerftTable, zobrist::HashMismatch, Error, Perft};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// Subtrees of smaller depth are cheap enough, so we don't split them further.
+const MIN_TASK_DEPTH: usize = 3;
+
+// We want many more tasks than threads, so all the threads stay busy until the end even if the
+// subtrees differ in size a lot.
+const TASKS_PER_THREAD: usize = 16;
+
+struct Task {
+    root_move: Option<String>,
+    fen: String,
+    depth: usize,
+}
+
+// Wrapper which splits the game tree into subtrees and runs the wrapped implementation on them
+// in multiple threads.
+pub struct Parallel<'a> {
+    inner: &'a dyn Perft,
+    threads: usize,
+}
+
+impl<'a> Parallel<'a> {
+    pub fn new(inner: &'a dyn Perft, threads: usize) -> Self {
+        assert!(threads > 0, "need at least one thread");
+        Self { inner, threads }
+    }
+
+    fn child_tasks(&self, task: &Task) -> Result<Vec<Task>, Error> {
+        let mut result = Vec::new();
+        for mv in self.inner.divide(&task.fen, 1)?.into_keys() {
+            result.push(Task {
+                fen: self.inner.make_move(&task.fen, &mv)?,
+                root_move: Some(task.root_move.clone().unwrap_or(mv)),
+                depth: task.depth - 1,
+            });
+        }
+        Ok(result)
+    }
+
+    fn split(&self, mut tasks: Vec<Task>) -> Result<Vec<Task>, Error> {
+        // All the tasks have the same depth, as we always split all of them at once.
+        while tasks.len() < self.threads * TASKS_PER_THREAD
+            && tasks.first().is_some_and(|t| t.depth > MIN_TASK_DEPTH)
+        {
+            let mut next = Vec::new();
+            for task in &tasks {
+                next.extend(self.child_tasks(task)?);
+            }
+            tasks = next;
+        }
+        Ok(tasks)
+    }
+
+    fn run<T: Default + Clone + Send>(
+        &self,
+        tasks: &[Task],
+        func: impl Fn(&str, usize) -> Result<T, Error> + Sync,
+    ) -> Result<Vec<T>, Error> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![T::default(); tasks.len()]);
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|_| {
+                    s.spawn(|| loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(task) = tasks.get(idx) else {
+                            return Ok(());
+                        };
+                        let value = func(&task.fen, task.depth)?;
+                        results.lock().unwrap()[idx] = value;
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|w| w.join().expect("worker thread panicked"))
+        })?;
+        Ok(results.into_inner().unwrap())
+    }
+
+    fn run_from_root<T: Default + Clone + Send>(
+        &self,
+        fen: &str,
+        depth: usize,
+        func: impl Fn(&str, usize) -> Result<T, Error> + Sync,
+    ) -> Result<Vec<T>, Error> {
+        let tasks = self.split(vec![Task {
+            root_move: None,
+            fen: fen.to_string(),
+            depth,
+        }])?;
+        self.run(&tasks, func)
+    }
+}
+
+impl Perft for Parallel<'_> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let results = self.run_from_root(fen, depth, |fen, depth| self.inner.perft(fen, depth))?;
+        Ok(results.into_iter().sum())
+    }
+
+    fn hperft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let results = self.run_from_root(fen, depth, |fen, depth| self.inner.hperft(fen, depth))?;
+        Ok(results.into_iter().fold(0, u64::wrapping_add))
+    }
+
+    fn hperft_v2(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        let results =
+            self.run_from_root(fen, depth, |fen, depth| self.inner.hperft_v2(fen, depth))?;
+        Ok(results.into_iter().fold(0, u64::wrapping_add))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        if depth == 0 {
+            return Ok(BTreeMap::new());
+        }
+        let root = Task {
+            root_move: None,
+            fen: fen.to_string(),
+            depth,
+        };
+        let roots = self.child_tasks(&root)?;
+        // Some root moves may not have any tasks after splitting, so we add them beforehand.
+        let mut result: BTreeMap<_, _> = roots
+            .iter()
+            .map(|t| (t.root_move.clone().unwrap(), 0))
+            .collect();
+        let tasks = self.split(roots)?;
+        let counts = self.run(&tasks, |fen, depth| self.inner.perft(fen, depth))?;
+        for (task, count) in tasks.iter().zip(counts) {
+            *result.get_mut(task.root_move.as_ref().unwrap()).unwrap() += count;
+        }
+        Ok(result)
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        self.inner.make_move(fen, mv)
+    }
+
+    // The table cannot be shared between threads, so we just run the implementation directly.
+    fn hashed_perft(&self, fen: &str, depth: usize, table: &mut PerftTable) -> Result<u64, Error> {
+        self.inner.hashed_perft(fen, depth, table)
+    }
+
+    fn perft_stats(&self, fen: &str, depth: usize) -> Result<PerftStats, Error> {
+        let results =
+            self.run_from_root(fen, depth, |fen, depth| self.inner.perft_stats(fen, depth))?;
+        let mut stats = PerftStats::default();
+        for r in results {
+            stats += r;
+        }
+        Ok(stats)
+    }
+
+    fn verify_hash(&self, fen: &str, depth: usize) -> Result<Option<HashMismatch>, Error> {
+        self.inner.verify_hash(fen, depth)
+    }
+
+    fn supports_chess960(&self) -> bool {
+        self.inner.supports_chess960()
+    }
+
+    fn perft_chess960(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        self.inner.perft_chess960(fen, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{impls, perft::CASES};
+
+    #[test]
+    fn test_parallel() {
+        for p in impls::all_perft() {
+            let par = Parallel::new(p.as_ref(), 3);
+            let cases = ["jordan_1", "jordan_9", "jordan_kiwipete", "pawn_promote"];
+            for case in CASES.iter().filter(|c| cases.contains(&c.name)) {
+                case.run_perft(&par).unwrap();
+                case.run_hperft(&par).unwrap();
+                case.run_hperft_v2(&par).unwrap();
+                case.run_perft_stats(&par).unwrap();
+                let divide = par.divide(case.fen, case.depth).unwrap();
+                assert_eq!(divide, p.divide(case.fen, case.depth).unwrap());
+            }
+        }
+    }
+}
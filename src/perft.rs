@@ -1,18 +1,105 @@
+use crate::Error;
+use std::ops::AddAssign;
+
+// Breakdown of the leaf nodes of the game tree, in the same format as the perft tables on
+// https://www.chessprogramming.org/Perft_Results. All the counters except `nodes` describe the
+// last move leading to the leaf and the resulting position.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub en_passants: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64,
+    pub stalemates: u64,
+}
+
+// Description of a leaf node, filled by the implementations.
+pub(crate) struct Leaf {
+    pub capture: bool,
+    pub en_passant: bool,
+    pub castle: bool,
+    pub promotion: bool,
+    // Bitboard of the pieces giving check.
+    pub checkers: u64,
+    // Bitboard of the squares which the pieces moved to. The check is discovered if it is given
+    // by some other piece. Bitboards may use any square numbering, as long as `checkers` and
+    // `moved` agree on it. Castling cannot give discovered check, so all the bits are set for it,
+    // i.e. the rook is always considered to be the checker.
+    pub moved: u64,
+    pub has_moves: bool,
+}
+
+impl PerftStats {
+    pub(crate) fn add_leaf(&mut self, leaf: Leaf) {
+        self.nodes += 1;
+        self.captures += leaf.capture as u64;
+        self.en_passants += leaf.en_passant as u64;
+        self.castles += leaf.castle as u64;
+        self.promotions += leaf.promotion as u64;
+        if leaf.checkers != 0 {
+            self.checks += 1;
+            // Following the chessprogramming.org tables, double checks are not counted as
+            // discovered ones.
+            match leaf.checkers.count_ones() {
+                1 => self.discovered_checks += (leaf.checkers & !leaf.moved != 0) as u64,
+                _ => self.double_checks += 1,
+            }
+            self.checkmates += !leaf.has_moves as u64;
+        } else {
+            self.stalemates += !leaf.has_moves as u64;
+        }
+    }
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.en_passants += other.en_passants;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.discovered_checks += other.discovered_checks;
+        self.double_checks += other.double_checks;
+        self.checkmates += other.checkmates;
+        self.stalemates += other.stalemates;
+    }
+}
+
 pub struct Case {
     pub name: &'static str,
     pub fen: &'static str,
     pub depth: usize,
     pub perft: u64,
     pub hperft: u64,
+    pub hperft_v2: u64,
+    pub stats: PerftStats,
 }
 
 impl Case {
-    pub fn run_perft<P: super::Perft + ?Sized>(&self, p: &P) {
-        assert_eq!(p.perft(self.fen, self.depth), self.perft);
+    pub fn run_perft<P: super::Perft + ?Sized>(&self, p: &P) -> Result<(), Error> {
+        assert_eq!(p.perft(self.fen, self.depth)?, self.perft);
+        Ok(())
+    }
+
+    pub fn run_hperft<P: super::Perft + ?Sized>(&self, p: &P) -> Result<(), Error> {
+        assert_eq!(p.hperft(self.fen, self.depth)?, self.hperft);
+        Ok(())
     }
 
-    pub fn run_hperft<P: super::Perft + ?Sized>(&self, p: &P) {
-        assert_eq!(p.hperft(self.fen, self.depth), self.hperft);
+    pub fn run_hperft_v2<P: super::Perft + ?Sized>(&self, p: &P) -> Result<(), Error> {
+        assert_eq!(p.hperft_v2(self.fen, self.depth)?, self.hperft_v2);
+        Ok(())
+    }
+
+    pub fn run_perft_stats<P: super::Perft + ?Sized>(&self, p: &P) -> Result<(), Error> {
+        assert_eq!(p.perft_stats(self.fen, self.depth)?, self.stats);
+        Ok(())
     }
 }
 
@@ -26,6 +113,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 824064,
         hperft: 10227354081862064469,
+        hperft_v2: 3415121515072992263,
+        stats: PerftStats {
+            nodes: 824064,
+            captures: 2942,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 34948,
+            discovered_checks: 4997,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_2",
@@ -33,6 +133,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 824064,
         hperft: 14960676359275113292,
+        hperft_v2: 7985891372109099996,
+        stats: PerftStats {
+            nodes: 824064,
+            captures: 2942,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 34948,
+            discovered_checks: 4997,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_3",
@@ -40,6 +153,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 1440467,
         hperft: 1507229866844926637,
+        hperft_v2: 17903459400634481704,
+        stats: PerftStats {
+            nodes: 1440467,
+            captures: 38889,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 19501,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_4",
@@ -47,6 +173,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 1440467,
         hperft: 15087435520595628865,
+        hperft_v2: 115479114775038235,
+        stats: PerftStats {
+            nodes: 1440467,
+            captures: 38889,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 19501,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_5",
@@ -54,6 +193,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 661072,
         hperft: 15048005469914942504,
+        hperft_v2: 5488206796972127410,
+        stats: PerftStats {
+            nodes: 661072,
+            captures: 7363,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_6",
@@ -61,6 +213,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 661072,
         hperft: 15950583300412830639,
+        hperft_v2: 9692202659317329377,
+        stats: PerftStats {
+            nodes: 661072,
+            captures: 7363,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_7",
@@ -68,6 +233,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 803711,
         hperft: 16122014333932527266,
+        hperft_v2: 5264414468679923325,
+        stats: PerftStats {
+            nodes: 803711,
+            captures: 7896,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_8",
@@ -75,6 +253,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 803711,
         hperft: 14451999952613291999,
+        hperft_v2: 14884142819298085922,
+        stats: PerftStats {
+            nodes: 803711,
+            captures: 7896,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_9",
@@ -82,6 +273,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1274206,
         hperft: 4641921541217416058,
+        hperft_v2: 937342231821160016,
+        stats: PerftStats {
+            nodes: 1274206,
+            captures: 81459,
+            en_passants: 0,
+            castles: 27208,
+            promotions: 0,
+            checks: 169219,
+            discovered_checks: 588,
+            double_checks: 72,
+            checkmates: 1408,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_10",
@@ -89,6 +293,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1274206,
         hperft: 14390205955143878532,
+        hperft_v2: 2894375092476459530,
+        stats: PerftStats {
+            nodes: 1274206,
+            captures: 81459,
+            en_passants: 0,
+            castles: 27208,
+            promotions: 0,
+            checks: 169219,
+            discovered_checks: 588,
+            double_checks: 72,
+            checkmates: 1408,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_11",
@@ -96,6 +313,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1720476,
         hperft: 15236009764005919001,
+        hperft_v2: 4588060473951735940,
+        stats: PerftStats {
+            nodes: 1720476,
+            captures: 100762,
+            en_passants: 0,
+            castles: 15602,
+            promotions: 0,
+            checks: 355248,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 2707,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_12",
@@ -103,6 +333,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1720476,
         hperft: 10737207666897534640,
+        hperft_v2: 14186066185152432602,
+        stats: PerftStats {
+            nodes: 1720476,
+            captures: 100762,
+            en_passants: 0,
+            castles: 15602,
+            promotions: 0,
+            checks: 355248,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 2707,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_13",
@@ -110,6 +353,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 3821001,
         hperft: 13688754110556353923,
+        hperft_v2: 8249856862609813634,
+        stats: PerftStats {
+            nodes: 3821001,
+            captures: 63626,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 309797,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_14",
@@ -117,6 +373,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 3821001,
         hperft: 12511139674264896147,
+        hperft_v2: 10291602103102346454,
+        stats: PerftStats {
+            nodes: 3821001,
+            captures: 63626,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 309797,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_15",
@@ -124,6 +393,19 @@ pub const CASES: [Case; 36] = [
         depth: 5,
         perft: 1004658,
         hperft: 8828821598830464170,
+        hperft_v2: 13345788433577805633,
+        stats: PerftStats {
+            nodes: 1004658,
+            captures: 26579,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 219282,
+            discovered_checks: 11232,
+            double_checks: 1346,
+            checkmates: 80,
+            stalemates: 148,
+        },
     },
     Case {
         name: "jordan_16",
@@ -131,6 +413,19 @@ pub const CASES: [Case; 36] = [
         depth: 5,
         perft: 1004658,
         hperft: 410996523585496144,
+        hperft_v2: 9189694277435843106,
+        stats: PerftStats {
+            nodes: 1004658,
+            captures: 26579,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 219282,
+            discovered_checks: 11232,
+            double_checks: 1346,
+            checkmates: 80,
+            stalemates: 148,
+        },
     },
     Case {
         name: "jordan_17",
@@ -138,6 +433,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 217342,
         hperft: 9965890832820219649,
+        hperft_v2: 979488870671686263,
+        stats: PerftStats {
+            nodes: 217342,
+            captures: 3833,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_18",
@@ -145,6 +453,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 217342,
         hperft: 3000710645582169111,
+        hperft_v2: 1452046687305348081,
+        stats: PerftStats {
+            nodes: 217342,
+            captures: 3833,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_19",
@@ -152,6 +473,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 92683,
         hperft: 6678614880857970379,
+        hperft_v2: 3934110449916412508,
+        stats: PerftStats {
+            nodes: 92683,
+            captures: 1832,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_20",
@@ -159,6 +493,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 92683,
         hperft: 2522239085604426516,
+        hperft_v2: 14486979853821303303,
+        stats: PerftStats {
+            nodes: 92683,
+            captures: 1832,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_21",
@@ -166,6 +513,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 2217,
         hperft: 965492357329846272,
+        hperft_v2: 17241903958015551455,
+        stats: PerftStats {
+            nodes: 2217,
+            captures: 1,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 29,
+        },
     },
     Case {
         name: "jordan_22",
@@ -173,6 +533,19 @@ pub const CASES: [Case; 36] = [
         depth: 6,
         perft: 2217,
         hperft: 10996353781449742,
+        hperft_v2: 8967532387302587640,
+        stats: PerftStats {
+            nodes: 2217,
+            captures: 1,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 29,
+        },
     },
     Case {
         name: "jordan_23",
@@ -180,6 +553,19 @@ pub const CASES: [Case; 36] = [
         depth: 7,
         perft: 567584,
         hperft: 16246619589065769502,
+        hperft_v2: 7829117963367621454,
+        stats: PerftStats {
+            nodes: 567584,
+            captures: 0,
+            en_passants: 0,
+            castles: 0,
+            promotions: 47528,
+            checks: 55501,
+            discovered_checks: 230,
+            double_checks: 0,
+            checkmates: 255,
+            stalemates: 1795,
+        },
     },
     Case {
         name: "jordan_24",
@@ -187,6 +573,19 @@ pub const CASES: [Case; 36] = [
         depth: 7,
         perft: 567584,
         hperft: 13529881500339651654,
+        hperft_v2: 7699348347537164817,
+        stats: PerftStats {
+            nodes: 567584,
+            captures: 0,
+            en_passants: 0,
+            castles: 0,
+            promotions: 47528,
+            checks: 55501,
+            discovered_checks: 230,
+            double_checks: 0,
+            checkmates: 255,
+            stalemates: 1795,
+        },
     },
     Case {
         name: "jordan_25",
@@ -194,6 +593,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 23527,
         hperft: 17574558369869797364,
+        hperft_v2: 3468523548324576404,
+        stats: PerftStats {
+            nodes: 23527,
+            captures: 1681,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_26",
@@ -201,6 +613,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 23527,
         hperft: 3863984453770373253,
+        hperft_v2: 2140220108436821,
+        stats: PerftStats {
+            nodes: 23527,
+            captures: 1681,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 0,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "jordan_kiwipete",
@@ -208,6 +633,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 4085603,
         hperft: 13273887749508334423,
+        hperft_v2: 15992384081810776035,
+        stats: PerftStats {
+            nodes: 4085603,
+            captures: 757163,
+            en_passants: 1929,
+            castles: 128013,
+            promotions: 15172,
+            checks: 25523,
+            discovered_checks: 42,
+            double_checks: 6,
+            checkmates: 43,
+            stalemates: 0,
+        },
     },
     Case {
         name: "initial",
@@ -215,6 +653,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 197281,
         hperft: 3599811434478483528,
+        hperft_v2: 6440763722289226971,
+        stats: PerftStats {
+            nodes: 197281,
+            captures: 1576,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 469,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 8,
+            stalemates: 0,
+        },
     },
     Case {
         name: "sicilian",
@@ -222,6 +673,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 2317898,
         hperft: 12556082293325863556,
+        hperft_v2: 9396814798907575940,
+        stats: PerftStats {
+            nodes: 2317898,
+            captures: 258770,
+            en_passants: 41,
+            castles: 48664,
+            promotions: 0,
+            checks: 30610,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 13,
+            stalemates: 0,
+        },
     },
     Case {
         name: "middle",
@@ -229,6 +693,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 2579062,
         hperft: 14747377813079023145,
+        hperft_v2: 15460384061655997628,
+        stats: PerftStats {
+            nodes: 2579062,
+            captures: 395476,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 10625,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 12,
+            stalemates: 0,
+        },
     },
     Case {
         name: "open_position",
@@ -236,6 +713,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 505064,
         hperft: 4437275209935405760,
+        hperft_v2: 12265142651704880103,
+        stats: PerftStats {
+            nodes: 505064,
+            captures: 31836,
+            en_passants: 0,
+            castles: 0,
+            promotions: 22972,
+            checks: 19968,
+            discovered_checks: 349,
+            double_checks: 283,
+            checkmates: 2,
+            stalemates: 0,
+        },
     },
     Case {
         name: "queen",
@@ -243,6 +733,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 211187,
         hperft: 12245621721721354430,
+        hperft_v2: 3564875970832318314,
+        stats: PerftStats {
+            nodes: 211187,
+            captures: 4600,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 51897,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 27,
+        },
     },
     Case {
         name: "pawn_move",
@@ -250,6 +753,19 @@ pub const CASES: [Case; 36] = [
         depth: 5,
         perft: 1683597,
         hperft: 7982926558036843904,
+        hperft_v2: 5431024321823968606,
+        stats: PerftStats {
+            nodes: 1683597,
+            captures: 16812,
+            en_passants: 236,
+            castles: 0,
+            promotions: 0,
+            checks: 12,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "pawn_attack",
@@ -257,6 +773,19 @@ pub const CASES: [Case; 36] = [
         depth: 5,
         perft: 1370744,
         hperft: 11192399975994366848,
+        hperft_v2: 8315355043088960702,
+        stats: PerftStats {
+            nodes: 1370744,
+            captures: 677859,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 18010,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
     Case {
         name: "pawn_promote",
@@ -264,6 +793,19 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1768584,
         hperft: 8207604282890666228,
+        hperft_v2: 17460293620530725268,
+        stats: PerftStats {
+            nodes: 1768584,
+            captures: 21176,
+            en_passants: 0,
+            castles: 0,
+            promotions: 1281992,
+            checks: 133738,
+            discovered_checks: 10138,
+            double_checks: 22,
+            checkmates: 3891,
+            stalemates: 0,
+        },
     },
     Case {
         name: "cydonia",
@@ -271,19 +813,32 @@ pub const CASES: [Case; 36] = [
         depth: 4,
         perft: 1962254,
         hperft: 7983221043579845606,
+        hperft_v2: 12583047109945491812,
+        stats: PerftStats {
+            nodes: 1962254,
+            captures: 133069,
+            en_passants: 0,
+            castles: 0,
+            promotions: 0,
+            checks: 117827,
+            discovered_checks: 0,
+            double_checks: 0,
+            checkmates: 0,
+            stalemates: 0,
+        },
     },
 ];
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::impls;
+    use crate::{impls, tt::PerftTable};
 
     #[test]
     fn test_perft() {
         for p in impls::all_perft() {
             for case in &CASES {
-                case.run_perft(p.as_ref());
+                case.run_perft(p.as_ref()).unwrap();
             }
         }
     }
@@ -292,7 +847,58 @@ mod tests {
     fn test_hperft() {
         for p in impls::all_perft() {
             for case in &CASES {
-                case.run_hperft(p.as_ref());
+                case.run_hperft(p.as_ref()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_hperft_v2() {
+        for p in impls::all_perft() {
+            for case in &CASES {
+                case.run_hperft_v2(p.as_ref()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_perft_stats() {
+        for p in impls::all_perft() {
+            for case in &CASES {
+                assert_eq!(case.stats.nodes, case.perft);
+                case.run_perft_stats(p.as_ref()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_hashed_perft() {
+        for p in impls::all_perft() {
+            let mut table = PerftTable::new(1 << 24);
+            for case in &CASES {
+                let count = p.hashed_perft(case.fen, case.depth, &mut table).unwrap();
+                assert_eq!(count, case.perft, "{} {}", p.name(), case.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_fen() {
+        for p in impls::all_perft() {
+            let err = p.perft("invalid fen", 1).unwrap_err();
+            assert_eq!(err.implementation(), p.name());
+        }
+    }
+
+    #[test]
+    fn test_divide() {
+        let perfts = impls::all_perft();
+        for case in &CASES {
+            let expected = perfts[0].divide(case.fen, case.depth).unwrap();
+            assert_eq!(expected.values().sum::<u64>(), case.perft);
+            for p in &perfts[1..] {
+                let divide = p.divide(case.fen, case.depth).unwrap();
+                assert_eq!(divide, expected, "{}", p.name());
             }
         }
     }
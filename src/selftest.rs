@@ -1,12 +1,34 @@
+use crate::{Error, MoveNotLegal};
 use arrayvec::ArrayVec;
-use std::io::{BufRead, Write};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    // One JSON object per line for each position.
+    Json,
+}
+
+#[derive(Clone, Debug)]
 pub struct Options {
     pub big_depth: bool,
     pub dump_trace_chains: bool,
     pub run_self_test: bool,
     pub attack_heatmaps: bool,
+    pub fen_round_trip: bool,
+    pub terminal_status: bool,
+    pub san: bool,
+    pub format: OutputFormat,
+    // Depth dumps to run instead of the default ones (see `depth_specs()`). Depth dumps with
+    // heatmaps require `attack_heatmaps` to be set.
+    pub custom_depth_specs: Option<Arc<[DepthSpec]>>,
 }
 
 impl Default for Options {
@@ -17,10 +39,83 @@ impl Default for Options {
             dump_trace_chains: false,
             run_self_test: true,
             attack_heatmaps: true,
+            fen_round_trip: false,
+            terminal_status: false,
+            san: false,
+            format: OutputFormat::Text,
+            custom_depth_specs: None,
+        }
+    }
+}
+
+impl Options {
+    // Depth dumps to run for each position, in the order of output.
+    pub fn depth_specs(&self) -> Vec<DepthSpec> {
+        if let Some(specs) = &self.custom_depth_specs {
+            return specs.to_vec();
+        }
+        let mut specs = vec![
+            DepthSpec {
+                depth: 1,
+                with_heatmaps: self.attack_heatmaps,
+            },
+            DepthSpec {
+                depth: 2,
+                with_heatmaps: false,
+            },
+        ];
+        if self.big_depth {
+            if self.attack_heatmaps {
+                specs.push(DepthSpec {
+                    depth: 2,
+                    with_heatmaps: true,
+                });
+            }
+            specs.push(DepthSpec {
+                depth: 3,
+                with_heatmaps: false,
+            });
         }
+        specs
     }
 }
 
+// Move string on which the UCI move parser disagrees with the legal move list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UciMismatch {
+    pub fen: String,
+    pub mv: String,
+    pub accepted: bool,
+}
+
+impl fmt::Display for UciMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.accepted {
+            true => write!(f, "{}: illegal move {} accepted", self.fen, self.mv),
+            false => write!(f, "{}: legal move {} rejected", self.fen, self.mv),
+        }
+    }
+}
+
+// All the from/to/promotion combinations, including the malformed ones.
+fn uci_candidates() -> &'static [String] {
+    static CANDIDATES: OnceLock<Vec<String>> = OnceLock::new();
+    CANDIDATES.get_or_init(|| {
+        let squares: Vec<_> = ('a'..='h')
+            .flat_map(|x| ('1'..='8').map(move |y| format!("{}{}", x, y)))
+            .collect();
+        let mut result = Vec::with_capacity(64 * 64 * 5);
+        for src in &squares {
+            for dst in &squares {
+                for promote in ["", "n", "b", "r", "q"] {
+                    result.push(format!("{}{}{}", src, dst, promote));
+                }
+            }
+        }
+        result
+    })
+}
+
 pub struct Tester<'a, T, W> {
     test: T,
     options: Options,
@@ -31,14 +126,17 @@ struct DepthCtx<'a> {
     spec: &'a DepthSpec,
     hash: u64,
     chain: String,
+    // Whether to write the move chain leading to each leaf.
+    dump_chains: bool,
 }
 
 impl<'a> DepthCtx<'a> {
-    fn new(spec: &'a DepthSpec) -> Self {
+    fn new(spec: &'a DepthSpec, dump_chains: bool) -> Self {
         Self {
             spec,
             hash: 0,
             chain: String::new(),
+            dump_chains,
         }
     }
 
@@ -48,13 +146,21 @@ impl<'a> DepthCtx<'a> {
     }
 }
 
-struct DepthSpec {
-    depth: usize,
-    with_heatmaps: bool,
+// FNV-1a hash, so the depth dump doesn't depend on the standard library hasher.
+fn fen_hash(fen: &str) -> u64 {
+    fen.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DepthSpec {
+    pub depth: usize,
+    pub with_heatmaps: bool,
 }
 
 impl DepthSpec {
-    fn name(&self) -> String {
+    pub fn name(&self) -> String {
         match self.with_heatmaps {
             true => format!("{}-heatmaps", self.depth),
             false => format!("{}", self.depth),
@@ -62,6 +168,176 @@ impl DepthSpec {
     }
 }
 
+// Parses the spec in the same format as returned by `DepthSpec::name()`, e.g. "3" or "4-heatmaps".
+impl FromStr for DepthSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (depth, with_heatmaps) = match s.strip_suffix("-heatmaps") {
+            Some(depth) => (depth, true),
+            None => (s, false),
+        };
+        let depth = depth
+            .parse()
+            .map_err(|_| format!("invalid depth spec \"{}\"", s))?;
+        Ok(Self {
+            depth,
+            with_heatmaps,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepthDump {
+    pub spec: DepthSpec,
+    pub hash: u64,
+}
+
+impl DepthDump {
+    fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "depth-dump-at-{}: {}", self.spec.name(), self.hash)
+    }
+}
+
+// Results of the selftest for one position. Optional fields are set only if the corresponding
+// options are enabled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub fen: String,
+    pub exported_fen: Option<String>,
+    // Legal moves in UCI notation, sorted.
+    pub moves: Vec<String>,
+    // Legal moves in UCI notation and in SAN, with the description of SAN round trip failure.
+    pub san: Option<Vec<(String, String, Option<String>)>>,
+    pub is_check: bool,
+    // Checkmate, stalemate and insufficient material, `None` if not supported.
+    pub terminal_status: Option<[Option<bool>; 3]>,
+    // Squares attacked by white and black, as bitboards with a1 = 0, b1 = 1, ..., h8 = 63.
+    pub heatmaps: Option<[u64; 2]>,
+    pub depth_dumps: Vec<DepthDump>,
+}
+
+fn bool_str(val: bool) -> &'static str {
+    match val {
+        true => "true",
+        false => "false",
+    }
+}
+
+impl Report {
+    pub fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+        self.write_text_node(w)?;
+        for dump in &self.depth_dumps {
+            dump.write_text(w)?;
+        }
+        writeln!(w)
+    }
+
+    // Writes everything except depth dumps.
+    fn write_text_node(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "fen: {}", self.fen)?;
+        if let Some(fen) = &self.exported_fen {
+            writeln!(w, "exported-fen: {}", fen)?;
+        }
+
+        writeln!(w, "moves: [")?;
+        for s in &self.moves {
+            writeln!(w, "  {}", s)?;
+        }
+        writeln!(w, "]")?;
+
+        if let Some(sans) = &self.san {
+            writeln!(w, "san: [")?;
+            for (uci, san, error) in sans {
+                match error {
+                    Some(error) => writeln!(w, "  {} {} ({})", uci, san, error)?,
+                    None => writeln!(w, "  {} {}", uci, san)?,
+                }
+            }
+            writeln!(w, "]")?;
+        }
+
+        writeln!(w, "check?: {}", bool_str(self.is_check))?;
+
+        if let Some(statuses) = self.terminal_status {
+            let names = ["checkmate?", "stalemate?", "insufficient-material?"];
+            for (name, status) in names.iter().zip(statuses) {
+                let status = status.map_or("unsupported", bool_str);
+                writeln!(w, "{}: {}", name, status)?;
+            }
+        }
+
+        if let Some(heatmaps) = self.heatmaps {
+            for (color_str, heatmap) in ["white", "black"].iter().zip(heatmaps) {
+                writeln!(w, "{}-heatmap: [", color_str)?;
+                for y in (0..8).rev() {
+                    write!(w, "  ")?;
+                    for x in 0..8 {
+                        match (heatmap >> (y * 8 + x)) & 1 != 0 {
+                            true => write!(w, "#")?,
+                            false => write!(w, ".")?,
+                        };
+                    }
+                    writeln!(w)?;
+                }
+                writeln!(w, "]")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the report as a single line of JSON.
+    pub fn write_json(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut fields = vec![("fen", json!(self.fen))];
+        if let Some(fen) = &self.exported_fen {
+            fields.push(("exported_fen", json!(fen)));
+        }
+        fields.push(("moves", json!(self.moves)));
+        if let Some(sans) = &self.san {
+            let sans = sans
+                .iter()
+                .map(|(uci, san, error)| json!({"move": uci, "san": san, "error": error}))
+                .collect();
+            fields.push(("san", Value::Array(sans)));
+        }
+        fields.push(("check", json!(self.is_check)));
+        if let Some([checkmate, stalemate, insufficient_material]) = self.terminal_status {
+            fields.push(("checkmate", json!(checkmate)));
+            fields.push(("stalemate", json!(stalemate)));
+            fields.push(("insufficient_material", json!(insufficient_material)));
+        }
+        if let Some([white, black]) = self.heatmaps {
+            fields.push(("white_heatmap", json!(white)));
+            fields.push(("black_heatmap", json!(black)));
+        }
+        let dumps = self
+            .depth_dumps
+            .iter()
+            .map(|dump| {
+                json!({
+                    "depth": dump.spec.depth,
+                    "heatmaps": dump.spec.with_heatmaps,
+                    "hash": dump.hash,
+                })
+            })
+            .collect();
+        fields.push(("depth_dumps", Value::Array(dumps)));
+        write_json_object(w, &fields)
+    }
+}
+
+// Unlike `serde_json::Map`, keeps the fields in the given order, so `fen` goes first.
+fn write_json_object(w: &mut impl Write, fields: &[(&str, Value)]) -> io::Result<()> {
+    write!(w, "{{")?;
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i != 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "{}:{}", json!(key), value)?;
+    }
+    writeln!(w, "}}")
+}
+
 impl<'a, T: crate::Test, W: Write> Tester<'a, T, W> {
     pub fn new(test: T, options: Options, writer: &'a mut W) -> Self {
         Self {
@@ -71,18 +347,23 @@ impl<'a, T: crate::Test, W: Write> Tester<'a, T, W> {
         }
     }
 
-    fn move_strings(&self, board: &mut T::Board, moves: &T::MoveList) -> Vec<String> {
+    fn legal_moves(&self, board: &mut T::Board) -> Vec<T::Move> {
         let t = &self.test;
 
-        let count = t.move_count(moves);
-        let mut result = Vec::with_capacity(count);
-        for i in 0..count {
-            let mv = t.get_move(moves, i);
-            if let Ok(u) = t.try_make_move(board, mv) {
-                result.push(t.move_str(mv));
+        let mut moves = Vec::new();
+        t.generate_moves(board, |mv| moves.push(mv));
+        moves.retain(|mv| match t.try_make_move(board, mv) {
+            Ok(u) => {
                 t.unmake_move(board, mv, &u);
+                true
             }
-        }
+            Err(MoveNotLegal) => false,
+        });
+        moves
+    }
+
+    fn move_strings(&self, moves: &[T::Move]) -> Vec<String> {
+        let mut result: Vec<_> = moves.iter().map(|mv| self.test.move_str(mv)).collect();
         result.sort();
         result
     }
@@ -108,11 +389,86 @@ impl<'a, T: crate::Test, W: Write> Tester<'a, T, W> {
         res
     }
 
-    fn depth_dump(&mut self, depth: usize, board: &mut T::Board, ctx: &mut DepthCtx) {
+    fn terminal_status(&self, board: &T::Board) -> [Option<bool>; 3] {
+        let t = &self.test;
+        [
+            t.is_checkmate(board),
+            t.is_stalemate(board),
+            t.is_insufficient_material(board),
+        ]
+    }
+
+    // Converts the legal move into SAN and parses it back. Returns the SAN and the description of
+    // round trip failure, if any.
+    fn san_round_trip(&self, board: &T::Board, mv: &T::Move) -> (String, Option<String>) {
         let t = &self.test;
+        let san = t
+            .move_to_san(board, mv)
+            .expect("implementation doesn't support SAN export");
+        let parsed = t
+            .move_from_san(board, &san)
+            .expect("implementation doesn't support SAN parsing");
+        let error = match parsed {
+            Err(MoveNotLegal) => Some("cannot parse".to_string()),
+            Ok(parsed) if t.move_str(&parsed) != t.move_str(mv) => {
+                Some(format!("parsed as {}", t.move_str(&parsed)))
+            }
+            Ok(parsed) => {
+                let new_san = t.move_to_san(board, &parsed).unwrap();
+                (new_san != san).then(|| format!("converted back as {}", new_san))
+            }
+        };
+        (san, error)
+    }
+
+    // Moves generated in the position, as returned by `move_hash()`, sorted. Unlike the legal move
+    // list, they are cheap to find, so they can be compared in each node.
+    fn generated_moves(&self, board: &T::Board) -> Vec<u64> {
+        let mut result = Vec::new();
+        self.test
+            .generate_moves(board, |mv| result.push(self.move_hash(&mv)));
+        result.sort();
+        result
+    }
+
+    // Exports the board into FEN and checks that importing it back gives the same position.
+    fn export_fen(&self, board: &T::Board) -> Result<String, Error> {
+        let t = &self.test;
+        let fen = t
+            .board_to_fen(board)
+            .expect("implementation doesn't support FEN export");
+        let restored = t.board_from_fen(&fen)?;
+        // Boards cannot be compared directly. Comparing their FENs is not enough either, as the
+        // information lost on import (e.g. castling rights) may also be missing from the export.
+        // So we also compare the moves and check flags in both positions.
+        let restored_fen = t.board_to_fen(&restored).unwrap();
+        let reason = if restored_fen != fen {
+            format!("restored position is exported as \"{}\"", restored_fen)
+        } else if self.generated_moves(board) != self.generated_moves(&restored) {
+            "moves differ".to_string()
+        } else if t.is_check(board) != t.is_check(&restored) {
+            "check flags differ".to_string()
+        } else {
+            return Ok(fen);
+        };
+        Err(Error::fen_round_trip(t.name(), &fen, reason))
+    }
+
+    fn depth_dump(
+        &mut self,
+        depth: usize,
+        board: &mut T::Board,
+        ctx: &mut DepthCtx,
+    ) -> Result<(), Error> {
+        let t = &self.test;
+
+        if self.options.fen_round_trip {
+            let fen = self.export_fen(board)?;
+            ctx.grow_hash(fen_hash(&fen));
+        }
 
         if depth == 0 {
-            if self.options.dump_trace_chains {
+            if ctx.dump_chains {
                 writeln!(self.writer, "cur-chain: {}", ctx.chain).unwrap();
             }
 
@@ -133,36 +489,44 @@ impl<'a, T: crate::Test, W: Write> Tester<'a, T, W> {
             }
 
             ctx.grow_hash(t.is_check(board) as u64);
+            if self.options.terminal_status {
+                for status in self.terminal_status(board) {
+                    ctx.grow_hash(match status {
+                        Some(val) => val as u64,
+                        None => 2,
+                    });
+                }
+            }
 
-            return;
+            return Ok(());
         }
 
-        let moves = t.generate_moves(board);
-        let count = t.move_count(&moves);
-        let mut move_ord: ArrayVec<(u64, usize), 256> = ArrayVec::new();
-        for i in 0..count {
-            let mv = t.get_move(&moves, i);
-            move_ord.push((self.move_hash(mv), i));
-        }
-        move_ord.sort();
+        let mut move_ord: ArrayVec<(u64, T::Move), 256> = ArrayVec::new();
+        t.generate_moves(board, |mv| move_ord.push((self.move_hash(&mv), mv)));
+        move_ord.sort_by_key(|&(val, _)| val);
 
         ctx.grow_hash(519365819);
-        for (val, idx) in move_ord {
+        for (val, mv) in move_ord {
             let t = &self.test;
-            let mv = t.get_move(&moves, idx);
             let old_len = ctx.chain.len();
-            if let Ok(u) = t.try_make_move(board, mv) {
-                if self.options.dump_trace_chains {
-                    ctx.chain += &(t.move_str(mv) + " ");
+            if let Ok(u) = t.try_make_move(board, &mv) {
+                if ctx.dump_chains {
+                    ctx.chain += &(t.move_str(&mv) + " ");
                 }
                 ctx.grow_hash(val);
-                self.depth_dump(depth - 1, board, ctx);
+                self.depth_dump(depth - 1, board, ctx)?;
                 let t = &self.test;
-                t.unmake_move(board, mv, &u);
+                t.unmake_move(board, &mv, &u);
+                if self.options.san {
+                    let (san, error) = self.san_round_trip(board, &mv);
+                    ctx.grow_hash(fen_hash(&san));
+                    ctx.grow_hash(error.is_some() as u64);
+                }
             }
             ctx.chain.truncate(old_len);
         }
         ctx.grow_hash(15967534195);
+        Ok(())
     }
 
     pub fn run_many<R: BufRead>(&mut self, reader: &mut R) {
@@ -172,94 +536,333 @@ impl<'a, T: crate::Test, W: Write> Tester<'a, T, W> {
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
-            self.run_one(line);
+            // Positions which cannot be parsed are reported and skipped, so that one bad FEN doesn't
+            // abort the whole run.
+            if let Err(e) = self.run_one(line) {
+                match self.options.format {
+                    OutputFormat::Text => {
+                        writeln!(self.writer, "fen: {}", line).unwrap();
+                        writeln!(self.writer, "error: {}", e).unwrap();
+                        writeln!(self.writer).unwrap();
+                    }
+                    OutputFormat::Json => {
+                        let fields = [("fen", json!(line)), ("error", json!(e.to_string()))];
+                        write_json_object(self.writer, &fields).unwrap();
+                    }
+                }
+            }
         }
     }
 
-    pub fn run_one(&mut self, fen: &str) {
+    // Feeds all the possible UCI move strings into the implementation's parser and compares the
+    // accepted ones with the legal move list.
+    pub fn check_uci_moves(&mut self, fen: &str) -> Result<Vec<UciMismatch>, Error> {
         let t = &self.test;
 
-        let mut board = t.board_from_fen(fen);
-        writeln!(self.writer, "fen: {}", fen).unwrap();
-        if self.options.run_self_test {
-            t.run_self_test(&board);
-        }
+        let mut board = t.board_from_fen(fen)?;
+        let moves = self.legal_moves(&mut board);
+        let legal: BTreeSet<_> = self.move_strings(&moves).into_iter().collect();
+        let accepted: BTreeSet<_> = uci_candidates()
+            .iter()
+            .filter(|s| {
+                self.test
+                    .is_uci_move_legal(&board, s)
+                    .expect("implementation doesn't support UCI move parsing")
+            })
+            .cloned()
+            .collect();
 
-        let moves = t.generate_moves(&board);
-        let move_strs = self.move_strings(&mut board, &moves);
+        Ok(legal
+            .symmetric_difference(&accepted)
+            .map(|mv| UciMismatch {
+                fen: fen.to_string(),
+                mv: mv.clone(),
+                accepted: accepted.contains(mv),
+            })
+            .collect())
+    }
 
-        writeln!(self.writer, "moves: [").unwrap();
-        for s in &move_strs {
-            writeln!(self.writer, "  {}", s).unwrap();
+    // Runs `check_uci_moves()` on every position and reports the mismatches. Returns the number of
+    // mismatches found.
+    pub fn check_uci_moves_many<R: BufRead>(&mut self, reader: &mut R) -> usize {
+        let mut count = 0;
+        for line in reader.lines() {
+            let line = line.expect("i/o error");
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match self.check_uci_moves(line) {
+                Ok(mismatches) => {
+                    for m in &mismatches {
+                        writeln!(self.writer, "{}", m).unwrap();
+                    }
+                    count += mismatches.len();
+                }
+                Err(e) => {
+                    writeln!(self.writer, "fen: {}", line).unwrap();
+                    writeln!(self.writer, "error: {}", e).unwrap();
+                }
+            }
         }
-        writeln!(self.writer, "]").unwrap();
-
-        let is_check = match t.is_check(&board) {
-            true => "true",
-            false => "false",
-        };
-        writeln!(self.writer, "check?: {}", is_check).unwrap();
+        count
+    }
 
-        if self.options.attack_heatmaps {
-            for color in [true, false] {
-                let color_str = match color {
-                    true => "white",
-                    false => "black",
-                };
-                writeln!(self.writer, "{}-heatmap: [", color_str).unwrap();
-                for y in ('1'..='8').rev() {
-                    write!(self.writer, "  ").unwrap();
-                    for x in 'a'..='h' {
-                        match t.is_attacked(&board, color, x, y) {
-                            true => write!(self.writer, "#").unwrap(),
-                            false => write!(self.writer, ".").unwrap(),
-                        };
-                    }
-                    writeln!(self.writer).unwrap();
+    fn heatmap(&self, board: &T::Board, color: bool) -> u64 {
+        let mut result = 0;
+        for (y, cy) in ('1'..='8').enumerate() {
+            for (x, cx) in ('a'..='h').enumerate() {
+                if self.test.is_attacked(board, color, cx, cy) {
+                    result |= 1 << (y * 8 + x);
                 }
-                writeln!(self.writer, "]").unwrap();
             }
         }
+        result
+    }
+
+    // Same as `report()`, but without depth dumps, so only the position itself is described.
+    pub fn report_node(&self, fen: &str) -> Result<Report, Error> {
+        let mut board = self.test.board_from_fen(fen)?;
+        self.node_report(fen, &mut board)
+    }
+
+    fn node_report(&self, fen: &str, board: &mut T::Board) -> Result<Report, Error> {
+        let t = &self.test;
+
+        let exported_fen = match self.options.fen_round_trip {
+            true => Some(self.export_fen(board)?),
+            false => None,
+        };
+        if self.options.run_self_test {
+            t.run_self_test(board);
+        }
+
+        let moves = self.legal_moves(board);
+
+        let san = self.options.san.then(|| {
+            let mut sans: Vec<_> = moves
+                .iter()
+                .map(|mv| {
+                    let (san, error) = self.san_round_trip(board, mv);
+                    (t.move_str(mv), san, error)
+                })
+                .collect();
+            sans.sort();
+            sans
+        });
+
+        let terminal_status = self
+            .options
+            .terminal_status
+            .then(|| self.terminal_status(board));
+        let heatmaps = self
+            .options
+            .attack_heatmaps
+            .then(|| [true, false].map(|color| self.heatmap(board, color)));
 
         if self.options.run_self_test {
-            for i in 0..t.move_count(&moves) {
-                let mv = t.get_move(&moves, i);
-                if let Ok(u) = t.try_make_move(&mut board, mv) {
-                    t.run_self_test(&board);
-                    t.unmake_move(&mut board, mv, &u);
+            for mv in &moves {
+                if let Ok(u) = t.try_make_move(board, mv) {
+                    t.run_self_test(board);
+                    t.unmake_move(board, mv, &u);
                 }
             }
         }
 
-        let mut specs = vec![
-            DepthSpec {
-                depth: 1,
-                with_heatmaps: self.options.attack_heatmaps,
-            },
-            DepthSpec {
-                depth: 2,
-                with_heatmaps: false,
-            },
-        ];
-        if self.options.big_depth {
-            if self.options.attack_heatmaps {
-                specs.push(DepthSpec {
-                    depth: 2,
-                    with_heatmaps: true,
-                });
+        Ok(Report {
+            fen: fen.to_string(),
+            exported_fen,
+            moves: self.move_strings(&moves),
+            san,
+            is_check: t.is_check(board),
+            terminal_status,
+            heatmaps,
+            depth_dumps: Vec::new(),
+        })
+    }
+
+    fn run_depth_dump(
+        &mut self,
+        spec: &DepthSpec,
+        board: &mut T::Board,
+        dump_chains: bool,
+    ) -> Result<DepthDump, Error> {
+        let mut ctx = DepthCtx::new(spec, dump_chains);
+        self.depth_dump(spec.depth, board, &mut ctx)?;
+        Ok(DepthDump {
+            spec: *spec,
+            hash: ctx.hash,
+        })
+    }
+
+    // Trace chains are not included into the report, they are only written by `run_one()`.
+    pub fn report(&mut self, fen: &str) -> Result<Report, Error> {
+        let mut board = self.test.board_from_fen(fen)?;
+        let mut report = self.node_report(fen, &mut board)?;
+        report.depth_dumps = self
+            .options
+            .depth_specs()
+            .iter()
+            .map(|spec| self.run_depth_dump(spec, &mut board, false))
+            .collect::<Result<_, Error>>()?;
+        Ok(report)
+    }
+
+    pub fn run_one(&mut self, fen: &str) -> Result<(), Error> {
+        if !self.options.dump_trace_chains || self.options.format != OutputFormat::Text {
+            let report = self.report(fen)?;
+            match self.options.format {
+                OutputFormat::Text => report.write_text(self.writer),
+                OutputFormat::Json => report.write_json(self.writer),
             }
-            specs.push(DepthSpec {
-                depth: 3,
-                with_heatmaps: false,
+            .unwrap();
+            return Ok(());
+        }
+
+        // Trace chains are huge, so they are not kept in memory, but written right away, before the
+        // hash of the corresponding depth dump.
+        let mut board = self.test.board_from_fen(fen)?;
+        let report = self.node_report(fen, &mut board)?;
+        report.write_text_node(self.writer).unwrap();
+        for spec in self.options.depth_specs() {
+            let dump = self.run_depth_dump(&spec, &mut board, true)?;
+            dump.write_text(self.writer).unwrap();
+        }
+        writeln!(self.writer).unwrap();
+        Ok(())
+    }
+}
+
+// Type-erased `Tester`, so the implementations can be enumerated at runtime (see
+// `impls::registry()`).
+pub trait DynTester: Sync {
+    fn run_many(&self, options: &Options, reader: &mut dyn BufRead, writer: &mut dyn Write);
+    fn check_uci_moves_many(
+        &self,
+        options: &Options,
+        reader: &mut dyn BufRead,
+        writer: &mut dyn Write,
+    ) -> usize;
+    fn report(&self, options: &Options, fen: &str) -> Result<Report, Error>;
+    fn report_node(&self, options: &Options, fen: &str) -> Result<Report, Error>;
+}
+
+impl<T: crate::Test + Copy + Sync> DynTester for T {
+    fn run_many(
+        &self,
+        options: &Options,
+        mut reader: &mut dyn BufRead,
+        mut writer: &mut dyn Write,
+    ) {
+        Tester::new(*self, options.clone(), &mut writer).run_many(&mut reader);
+    }
+
+    fn check_uci_moves_many(
+        &self,
+        options: &Options,
+        mut reader: &mut dyn BufRead,
+        mut writer: &mut dyn Write,
+    ) -> usize {
+        Tester::new(*self, options.clone(), &mut writer).check_uci_moves_many(&mut reader)
+    }
+
+    fn report(&self, options: &Options, fen: &str) -> Result<Report, Error> {
+        let mut sink = io::sink();
+        Tester::new(*self, options.clone(), &mut sink).report(fen)
+    }
+
+    fn report_node(&self, options: &Options, fen: &str) -> Result<Report, Error> {
+        let mut sink = io::sink();
+        Tester::new(*self, options.clone(), &mut sink).report_node(fen)
+    }
+}
+
+// Same as `DynTester::run_many()`, but spreads the positions across `jobs` worker threads. The
+// results are written in the input order, so the output is exactly the same as the sequential one.
+pub fn run_many_parallel(
+    test: &dyn DynTester,
+    options: &Options,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+    jobs: usize,
+) {
+    assert!(jobs > 0, "need at least one job");
+    let lines: Vec<String> = reader
+        .lines()
+        .map(|l| l.expect("i/o error"))
+        .filter(|l| {
+            let l = l.trim_end();
+            !l.is_empty() && !l.starts_with('#')
+        })
+        .collect();
+    let next = AtomicUsize::new(0);
+    thread::scope(|s| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (lines, next) = (&lines, &next);
+            s.spawn(move || loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(line) = lines.get(idx) else {
+                    return;
+                };
+                let mut output = Vec::new();
+                test.run_many(options, &mut line.as_bytes(), &mut output);
+                if sender.send((idx, output)).is_err() {
+                    return;
+                }
             });
         }
+        drop(sender);
 
-        for spec in &specs {
-            let mut ctx = DepthCtx::new(spec);
-            self.depth_dump(spec.depth, &mut board, &mut ctx);
-            writeln!(self.writer, "depth-dump-at-{}: {}", spec.name(), ctx.hash).unwrap();
+        // The results may come out of order, so we keep them until all the preceding ones arrive.
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        for (idx, output) in receiver {
+            pending.insert(idx, output);
+            while let Some(output) = pending.remove(&written) {
+                writer.write_all(&output).unwrap();
+                written += 1;
+            }
         }
+        assert_eq!(written, lines.len(), "worker thread panicked");
+    });
+}
 
-        writeln!(self.writer).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::LossyFenTest;
+
+    #[test]
+    fn test_fen_round_trip_failure() {
+        let options = Options {
+            fen_round_trip: true,
+            big_depth: false,
+            ..Default::default()
+        };
+        let fens = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1\n4k3/8/8/8/8/8/8/4K3 w - - 0 1\n";
+        let mut output = Vec::new();
+        Tester::new(LossyFenTest, options, &mut output).run_many(&mut fens.as_bytes());
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next(),
+            Some("fen: r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1")
+        );
+        assert_eq!(
+            lines.next(),
+            Some(
+                "error: lossy: fen round trip failed for \"r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1\": \
+                 moves differ"
+            )
+        );
+        assert_eq!(lines.next(), Some(""));
+        // The next position is still checked.
+        assert_eq!(lines.next(), Some("fen: 4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+        assert_eq!(
+            lines.next(),
+            Some("exported-fen: 4k3/8/8/8/8/8/8/4K3 w - - 0 1")
+        );
     }
 }
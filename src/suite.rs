@@ -0,0 +1,212 @@
+use crate::{Error, Perft};
+use std::{fmt, fs, io, path::Path};
+
+// Position from a perft suite, with the expected perft results for several depths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuiteCase {
+    pub fen: String,
+    pub expected: Vec<(usize, u64)>,
+}
+
+#[derive(Debug)]
+pub enum SuiteError {
+    Io(io::Error),
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for SuiteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "cannot read suite: {}", e),
+            Self::Parse { line, reason } => write!(f, "line {}: {}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for SuiteError {}
+
+impl From<io::Error> for SuiteError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn parse_depth(item: &str) -> Result<(usize, u64), String> {
+    let (depth, count) = item
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("bad depth item \"{}\"", item))?;
+    let depth = depth
+        .strip_prefix('D')
+        .and_then(|d| d.parse().ok())
+        .ok_or_else(|| format!("bad depth \"{}\"", depth))?;
+    let count = count
+        .trim()
+        .parse()
+        .map_err(|_| format!("bad perft value \"{}\"", count.trim()))?;
+    Ok((depth, count))
+}
+
+fn parse_line(line: &str) -> Result<SuiteCase, String> {
+    let mut items = line.split(';').map(str::trim);
+    let fen = items.next().unwrap();
+    // EPD positions may lack move counters, while some implementations require them.
+    let fen = match fen.split_whitespace().count() {
+        4 => format!("{} 0 1", fen),
+        6 => fen.to_string(),
+        _ => return Err(format!("bad fen \"{}\"", fen)),
+    };
+    let expected = items
+        .filter(|item| !item.is_empty())
+        .map(parse_depth)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(SuiteCase { fen, expected })
+}
+
+// Parses perft suite in EPD format, i.e. lines like `<fen> ;D1 20 ;D2 400`. Empty lines and lines
+// starting with `#` are ignored.
+pub fn parse_epd(data: &str) -> Result<Vec<SuiteCase>, SuiteError> {
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(idx, line)| {
+            parse_line(line).map_err(|reason| SuiteError::Parse {
+                line: idx + 1,
+                reason,
+            })
+        })
+        .collect()
+}
+
+pub fn load_epd(path: impl AsRef<Path>) -> Result<Vec<SuiteCase>, SuiteError> {
+    parse_epd(&fs::read_to_string(path)?)
+}
+
+pub struct Failure {
+    pub implementation: &'static str,
+    pub fen: String,
+    pub depth: usize,
+    pub expected: u64,
+    pub actual: Result<u64, Error>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: \"{}\" depth {}: expected {}, ",
+            self.implementation, self.fen, self.depth, self.expected
+        )?;
+        match &self.actual {
+            Ok(actual) => write!(f, "got {}", actual),
+            Err(e) => write!(f, "got error: {}", e),
+        }
+    }
+}
+
+fn run_with(
+    p: &dyn Perft,
+    cases: &[SuiteCase],
+    max_depth: Option<usize>,
+    perft: impl Fn(&str, usize) -> Result<u64, Error>,
+) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for case in cases {
+        for &(depth, expected) in &case.expected {
+            if max_depth.is_some_and(|max| depth > max) {
+                continue;
+            }
+            let actual = perft(&case.fen, depth);
+            if actual.as_ref() != Ok(&expected) {
+                failures.push(Failure {
+                    implementation: p.name(),
+                    fen: case.fen.clone(),
+                    depth,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    failures
+}
+
+// Runs perft for all the cases and returns the results which differ from the expected ones.
+// Depths greater than `max_depth` are skipped.
+pub fn run_suite(p: &dyn Perft, cases: &[SuiteCase], max_depth: Option<usize>) -> Vec<Failure> {
+    run_with(p, cases, max_depth, |fen, depth| p.perft(fen, depth))
+}
+
+// Same as `run_suite()`, but runs perft in Chess960 mode.
+pub fn run_suite_chess960(
+    p: &dyn Perft,
+    cases: &[SuiteCase],
+    max_depth: Option<usize>,
+) -> Vec<Failure> {
+    run_with(p, cases, max_depth, |fen, depth| {
+        p.perft_chess960(fen, depth)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::impls;
+
+    const SUITE: &str = "\
+# Comments and empty lines are skipped
+
+rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902
+r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - ;D1 48 ;D2 2039
+";
+
+    #[test]
+    fn test_parse() {
+        let cases = parse_epd(SUITE).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].expected, [(1, 20), (2, 400), (3, 8902)]);
+        assert_eq!(
+            cases[1].fen,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"
+        );
+
+        let err = parse_epd("8/8/8/8/8/8/8/8 w - - 0 1 ;D1 x").unwrap_err();
+        assert!(matches!(err, SuiteError::Parse { line: 1, .. }));
+        let err = parse_epd("\n8/8/8/8/8/8/8/8 w - - 0 1 ;1 20").unwrap_err();
+        assert!(matches!(err, SuiteError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn test_run_suite() {
+        let cases = parse_epd(SUITE).unwrap();
+        for p in impls::all_perft() {
+            assert!(run_suite(p.as_ref(), &cases, None).is_empty());
+        }
+
+        let wrong = parse_epd(&SUITE.replace("D2 400", "D2 401")).unwrap();
+        let failures = run_suite(&impls::chess::Perft, &wrong, Some(2));
+        assert_eq!(failures.len(), 1);
+        assert_eq!((failures[0].depth, failures[0].expected), (2, 401));
+        assert_eq!(failures[0].actual, Ok(400));
+    }
+
+    #[test]
+    fn test_run_suite_chess960() {
+        // The same position in Shredder-FEN and in X-FEN.
+        let cases = parse_epd(
+            "\
+bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9 ;D1 21 ;D2 528 ;D3 12189
+bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KFkf - 2 9 ;D1 21 ;D2 528 ;D3 12189
+",
+        )
+        .unwrap();
+        for p in impls::all_perft() {
+            let failures = run_suite_chess960(p.as_ref(), &cases, None);
+            match p.supports_chess960() {
+                true => assert!(failures.is_empty(), "{}", p.name()),
+                false => assert!(failures
+                    .iter()
+                    .all(|f| matches!(f.actual, Err(Error::Unsupported { .. })))),
+            }
+        }
+    }
+}
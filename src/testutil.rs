@@ -0,0 +1,163 @@
+use crate::{impls::mailbox, Error, MoveNotLegal, Perft, Test};
+use std::collections::BTreeMap;
+
+// Wraps a perft implementation and pretends that the move `mv` is illegal in position `fen`.
+pub struct Broken<'a> {
+    pub inner: &'a dyn Perft,
+    pub fen: &'a str,
+    pub mv: &'a str,
+}
+
+impl Broken<'_> {
+    fn moves(&self, fen: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .inner
+            .divide(fen, 1)?
+            .into_keys()
+            .filter(|mv| fen != self.fen || mv != self.mv)
+            .collect())
+    }
+}
+
+impl Perft for Broken<'_> {
+    fn name(&self) -> &'static str {
+        "broken"
+    }
+
+    fn perft(&self, fen: &str, depth: usize) -> Result<u64, Error> {
+        match depth {
+            0 => Ok(1),
+            _ => Ok(self.divide(fen, depth)?.values().sum()),
+        }
+    }
+
+    fn hperft(&self, _fen: &str, _depth: usize) -> Result<u64, Error> {
+        Err(Error::unsupported(self.name(), "hperft"))
+    }
+
+    fn divide(&self, fen: &str, depth: usize) -> Result<BTreeMap<String, u64>, Error> {
+        if depth == 0 {
+            return Ok(BTreeMap::new());
+        }
+        self.moves(fen)?
+            .into_iter()
+            .map(|mv| {
+                let count = self.perft(&self.make_move(fen, &mv)?, depth - 1)?;
+                Ok((mv, count))
+            })
+            .collect()
+    }
+
+    fn make_move(&self, fen: &str, mv: &str) -> Result<String, Error> {
+        self.inner.make_move(fen, mv)
+    }
+}
+
+// Same as `Broken`, but for selftest. Wraps `mailbox`, as it can export FEN to identify the
+// position.
+#[derive(Clone, Copy)]
+pub struct BrokenTest {
+    pub fen: &'static str,
+    pub mv: &'static str,
+}
+
+impl Test for BrokenTest {
+    type Board = <mailbox::Test as Test>::Board;
+    type Move = <mailbox::Test as Test>::Move;
+    type Undo = <mailbox::Test as Test>::Undo;
+
+    fn name(&self) -> &'static str {
+        "broken"
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        mailbox::Test.board_from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        mailbox::Test.try_make_move(board, mv)
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, mv: &Self::Move, u: &Self::Undo) {
+        mailbox::Test.unmake_move(board, mv, u)
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        mailbox::Test.move_str(mv)
+    }
+
+    fn generate_moves(&self, b: &Self::Board, mut visit: impl FnMut(Self::Move)) {
+        let is_broken = mailbox::Test.board_to_fen(b).unwrap() == self.fen;
+        mailbox::Test.generate_moves(b, |mv| {
+            if !is_broken || mailbox::Test.move_str(&mv) != self.mv {
+                visit(mv);
+            }
+        });
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        mailbox::Test.is_attacked(b, is_white, cx, cy)
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        mailbox::Test.is_check(b)
+    }
+}
+
+// Wraps `mailbox`, but loses castling rights on FEN export, so the exported FEN survives the round
+// trip while the position doesn't.
+#[derive(Clone, Copy)]
+pub struct LossyFenTest;
+
+impl Test for LossyFenTest {
+    type Board = <mailbox::Test as Test>::Board;
+    type Move = <mailbox::Test as Test>::Move;
+    type Undo = <mailbox::Test as Test>::Undo;
+
+    fn name(&self) -> &'static str {
+        "lossy"
+    }
+
+    fn board_from_fen(&self, fen: &str) -> Result<Self::Board, Error> {
+        mailbox::Test.board_from_fen(fen)
+    }
+
+    fn try_make_move(
+        &self,
+        board: &mut Self::Board,
+        mv: &Self::Move,
+    ) -> Result<Self::Undo, MoveNotLegal> {
+        mailbox::Test.try_make_move(board, mv)
+    }
+
+    fn unmake_move(&self, board: &mut Self::Board, mv: &Self::Move, u: &Self::Undo) {
+        mailbox::Test.unmake_move(board, mv, u)
+    }
+
+    fn move_str(&self, mv: &Self::Move) -> String {
+        mailbox::Test.move_str(mv)
+    }
+
+    fn generate_moves(&self, b: &Self::Board, visit: impl FnMut(Self::Move)) {
+        mailbox::Test.generate_moves(b, visit)
+    }
+
+    fn is_attacked(&self, b: &Self::Board, is_white: bool, cx: char, cy: char) -> bool {
+        mailbox::Test.is_attacked(b, is_white, cx, cy)
+    }
+
+    fn is_check(&self, b: &Self::Board) -> bool {
+        mailbox::Test.is_check(b)
+    }
+
+    fn board_to_fen(&self, b: &Self::Board) -> Option<String> {
+        let fen = mailbox::Test.board_to_fen(b)?;
+        let mut fields: Vec<_> = fen.split(' ').collect();
+        fields[2] = "-";
+        Some(fields.join(" "))
+    }
+}
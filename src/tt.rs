@@ -0,0 +1,38 @@
+use std::mem;
+
+#[derive(Copy, Clone, Default)]
+struct Entry {
+    hash: u64,
+    depth: usize,
+    count: u64,
+}
+
+// Cache for perft results, keyed by position hash and depth. Each implementation uses its own
+// hash function, so the table must not be shared between different implementations.
+pub struct PerftTable {
+    entries: Vec<Entry>,
+    mask: usize,
+}
+
+impl PerftTable {
+    // Creates a table which occupies at most `mem_bytes` bytes of memory.
+    pub fn new(mem_bytes: usize) -> Self {
+        let count = (mem_bytes / mem::size_of::<Entry>()).max(1);
+        let count = 1 << count.ilog2();
+        Self {
+            entries: vec![Entry::default(); count],
+            mask: count - 1,
+        }
+    }
+
+    pub fn get(&self, hash: u64, depth: usize) -> Option<u64> {
+        let entry = &self.entries[hash as usize & self.mask];
+        // Entries with zero depth are never stored, so empty entries never match.
+        (entry.hash == hash && entry.depth == depth).then_some(entry.count)
+    }
+
+    pub fn insert(&mut self, hash: u64, depth: usize, count: u64) {
+        assert_ne!(depth, 0);
+        self.entries[hash as usize & self.mask] = Entry { hash, depth, count };
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+// Position where the incrementally updated hash differs from the hash of the same position built
+// from scratch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashMismatch {
+    pub implementation: &'static str,
+    pub path: Vec<String>,
+    pub fen: String,
+    pub incremental: u64,
+    pub rebuilt: u64,
+}
+
+impl HashMismatch {
+    pub(crate) fn check(
+        implementation: &'static str,
+        path: &[String],
+        fen: String,
+        incremental: u64,
+        rebuilt: u64,
+    ) -> Option<Self> {
+        (incremental != rebuilt).then(|| Self {
+            implementation,
+            path: path.to_vec(),
+            fen,
+            incremental,
+            rebuilt,
+        })
+    }
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: hash mismatch", self.implementation)?;
+        writeln!(f, "path: [{}]", self.path.join(" "))?;
+        writeln!(f, "fen: {}", self.fen)?;
+        write!(
+            f,
+            "incremental: {:016x}, rebuilt from fen: {:016x}",
+            self.incremental, self.rebuilt
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{impls, perft::CASES};
+
+    #[test]
+    fn test_verify_hash() {
+        for p in impls::all_perft() {
+            for case in &CASES {
+                let mismatch = p.verify_hash(case.fen, case.depth.min(3)).unwrap();
+                assert_eq!(mismatch, None, "{} {}", p.name(), case.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let path = ["e2e4".to_string()];
+        assert_eq!(HashMismatch::check("x", &path, "fen".into(), 1, 1), None);
+        let mismatch = HashMismatch::check("x", &path, "fen".into(), 1, 2).unwrap();
+        assert_eq!(mismatch.path, path);
+        assert_eq!(
+            mismatch.to_string(),
+            "x: hash mismatch\npath: [e2e4]\nfen: fen\n\
+             incremental: 0000000000000001, rebuilt from fen: 0000000000000002"
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use chess_bench::{impls, suite};
+
+const SUITE: &str = include_str!("chess960.epd");
+const MAX_DEPTH: usize = 4;
+
+// Checks the implementations supporting Chess960 against the suite. Deeper entries are skipped, as
+// they take too long.
+#[test]
+fn test_chess960() {
+    let cases = suite::parse_epd(SUITE).unwrap();
+    let mut failed = false;
+    for p in impls::all_perft() {
+        if !p.supports_chess960() {
+            continue;
+        }
+        for failure in suite::run_suite_chess960(p.as_ref(), &cases, Some(MAX_DEPTH)) {
+            eprintln!("{}", failure);
+            failed = true;
+        }
+    }
+    assert!(!failed, "some chess960 suite entries failed");
+}
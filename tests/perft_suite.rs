@@ -0,0 +1,25 @@
+use chess_bench::{impls, suite};
+use std::env;
+
+// Checks all the implementations against the perft suite from the file given in `PERFT_SUITE`
+// environment variable. Set `PERFT_SUITE_DEPTH` to skip the deeper entries. If `PERFT_SUITE` is
+// not set, the test does nothing.
+#[test]
+fn test_perft_suite() {
+    let Ok(path) = env::var("PERFT_SUITE") else {
+        eprintln!("PERFT_SUITE is not set, skipping");
+        return;
+    };
+    let max_depth = env::var("PERFT_SUITE_DEPTH")
+        .ok()
+        .map(|d| d.parse().expect("bad PERFT_SUITE_DEPTH"));
+    let cases = suite::load_epd(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let mut failed = false;
+    for p in impls::all_perft() {
+        for failure in suite::run_suite(p.as_ref(), &cases, max_depth) {
+            eprintln!("{}", failure);
+            failed = true;
+        }
+    }
+    assert!(!failed, "some perft suite entries failed");
+}
@@ -1,26 +1,394 @@
-use chess_bench::{impls, selftest::Tester, Test};
+use chess_bench::{
+    diff, golden,
+    impls::{self, Implementation},
+    selftest::{self, DepthSpec, DynTester, Options, OutputFormat, Tester},
+};
 use hex_literal::hex;
+use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
+use std::{io, thread};
 
 const INPUT_DATA: &str = include_str!("boards.fen");
 const OUTPUT_HASH: [u8; 32] =
     hex!("1ac232af9c1ede66b0cf423c87838324b09d178a5721b2c4ded7d87540a96318");
 
-fn run_test<T: Test>(test: T) {
+// Digests of the output for each position, so a failing run can tell which positions broke. To
+// regenerate, run `cargo run --release --bin selftest -- golden mailbox <tests/boards.fen
+// >tests/boards.golden`.
+const GOLDEN: &str = include_str!("boards.golden");
+const MAX_REPORTED_FENS: usize = 10;
+
+// FEN round trip, terminal status and SAN checks are slower, so we run them only on the beginning
+// of the input. Only the implementations supporting these features are checked.
+const PREFIX_LINES: usize = 300;
+const FEN_ROUND_TRIP_HASH: [u8; 32] =
+    hex!("6f992729a6e4d7e631cb1e7fde0a7c8391b252276366ae46fc582ad7c52a1d50");
+const TERMINAL_STATUS_HASH: [u8; 32] =
+    hex!("38a8e2ec3961f10f2d9174ecf1af4b0a1bfda11a47dc39c5c58e9c76c59f2a04");
+const SAN_HASH: [u8; 32] = hex!("27c0179a45f3f5f7c4de3c114a626bebfdff37abf31706465bb1f537bb1f8a0b");
+// Output with the default options on the beginning of the input.
+const PREFIX_HASH: [u8; 32] =
+    hex!("40cd8bcb23d092a128a4ad827c9376545a83654922f95ab190673a8e4e97319d");
+
+// Runs `check` on all the implementations selected by `filter` in parallel, and fails if any of
+// them fails.
+fn check_all(
+    filter: impl Fn(&Implementation) -> bool,
+    check: impl Fn(&dyn DynTester) -> Result<(), String> + Sync,
+) {
+    let impls: Vec<_> = impls::registry()
+        .into_iter()
+        .filter(|imp| imp.selftest.is_some() && filter(imp))
+        .collect();
+    let failures: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = impls
+            .iter()
+            .map(|imp| {
+                let check = &check;
+                s.spawn(move || {
+                    check(imp.selftest.as_deref().unwrap())
+                        .map_err(|e| format!("{}: {}", imp.name, e))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|h| h.join().unwrap().err())
+            .collect()
+    });
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+fn check_hash(
+    test: &dyn DynTester,
+    options: &Options,
+    input: &str,
+    expected: &[u8; 32],
+) -> Result<(), String> {
     let mut hasher = Sha256::default();
-    let mut tester = Tester::new(test, Default::default(), &mut hasher);
-    tester.run_many(&mut INPUT_DATA.as_bytes());
+    test.run_many(options, &mut input.as_bytes(), &mut hasher);
     // Here, we only verify the output hash. To debug your code, use the command-line
     // utility `selftest`.
-    assert_eq!(&hasher.finalize()[..], &OUTPUT_HASH[..]);
+    let hash = hasher.finalize();
+    match hash[..] == expected[..] {
+        true => Ok(()),
+        false => Err(format!("output hash mismatch: {:x}", hash)),
+    }
+}
+
+fn prefix() -> String {
+    INPUT_DATA
+        .lines()
+        .take(PREFIX_LINES)
+        .map(|l| l.to_string() + "\n")
+        .collect()
+}
+
+// Same as `check_hash()`, but also compares the output for each position with the golden file.
+fn check_golden(test: &dyn DynTester) -> Result<(), String> {
+    let mut hasher = Sha256::default();
+    let mut digests = Vec::new();
+    golden::for_each_position(
+        test,
+        &Default::default(),
+        &mut INPUT_DATA.as_bytes(),
+        |fen, output| {
+            hasher.update(output);
+            digests.push((fen.to_string(), golden::digest(output)));
+        },
+    );
+    let golden = golden::parse_golden(GOLDEN).expect("bad golden file");
+    let failed = golden::mismatches(&digests, &golden);
+    if !failed.is_empty() {
+        let groups = [
+            (
+                &failed.changed,
+                digests.len(),
+                "differ from the golden file",
+            ),
+            (&failed.extra, digests.len(), "are not in the golden file"),
+            (
+                &failed.missing,
+                golden.len(),
+                "from the golden file are not in the input",
+            ),
+        ];
+        let mut message = String::new();
+        for (fens, total, what) in groups.into_iter().filter(|(fens, ..)| !fens.is_empty()) {
+            let shown: Vec<_> = fens.iter().take(MAX_REPORTED_FENS).copied().collect();
+            message += &format!(
+                "{} of {} positions {}, first ones:\n{}\n",
+                fens.len(),
+                total,
+                what,
+                shown.join("\n")
+            );
+        }
+        return Err(message);
+    }
+    let hash = hasher.finalize();
+    match hash[..] == OUTPUT_HASH[..] {
+        true => Ok(()),
+        false => Err(format!("output hash mismatch: {:x}", hash)),
+    }
+}
+
+#[test]
+fn test_selftest() {
+    check_all(|_| true, check_golden);
+}
+
+#[test]
+fn test_fen_round_trip() {
+    let options = Options {
+        fen_round_trip: true,
+        ..Default::default()
+    };
+    let input = prefix();
+    check_all(
+        |imp| imp.caps.fen_export,
+        |t| check_hash(t, &options, &input, &FEN_ROUND_TRIP_HASH),
+    );
+}
+
+#[test]
+fn test_terminal_status() {
+    let options = Options {
+        terminal_status: true,
+        ..Default::default()
+    };
+    let input = prefix();
+    check_all(
+        |imp| imp.caps.terminal_status,
+        |t| check_hash(t, &options, &input, &TERMINAL_STATUS_HASH),
+    );
+}
+
+// Implementations which support only some of the statuses are not covered by `test_terminal_status`,
+// so we compare the statuses they support with the reference. The whole input is used here, as the
+// beginning of it has no terminal positions.
+#[test]
+fn test_partial_terminal_status() {
+    // Only the statuses are of interest here, the rest is covered by the other tests.
+    let options = Options {
+        terminal_status: true,
+        run_self_test: false,
+        attack_heatmaps: false,
+        ..Default::default()
+    };
+    let fens: Vec<_> = INPUT_DATA
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+    check_all(
+        |_| true,
+        |t| {
+            for fen in &fens {
+                let Ok(expected) = impls::mailbox::Test.report_node(&options, fen) else {
+                    continue;
+                };
+                let actual = t.report_node(&options, fen).map_err(|e| e.to_string())?;
+                if actual.terminal_status.is_none() {
+                    return Err("no terminal status reported".to_string());
+                }
+                if let Some((field, _)) = diff::compare_reports(&expected, &actual) {
+                    return Err(format!("{} differs for {}", field, fen));
+                }
+            }
+            Ok(())
+        },
+    );
+}
+
+#[test]
+fn test_uci_moves() {
+    check_all(
+        |imp| imp.caps.uci_moves,
+        |t| {
+            let mut output = Vec::new();
+            let count = t.check_uci_moves_many(
+                &Default::default(),
+                &mut INPUT_DATA.as_bytes(),
+                &mut output,
+            );
+            match count {
+                0 => Ok(()),
+                _ => Err(String::from_utf8_lossy(&output).into_owned()),
+            }
+        },
+    );
+}
+
+// UCI parsers are known to accept illegal strings in these positions, e.g. castling written as
+// "king captures rook" (`e1h1`) or en passant capture with promotion piece (`e5d6q`).
+const UCI_QUIRKS: &str = "\
+r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1
+r3k2r/8/8/8/3Pp3/8/8/R3K2R b KQkq d3 0 1
+";
+
+#[test]
+fn test_uci_move_quirks() {
+    check_all(
+        |imp| imp.caps.uci_moves,
+        |t| {
+            let mut output = Vec::new();
+            let count = t.check_uci_moves_many(
+                &Default::default(),
+                &mut UCI_QUIRKS.as_bytes(),
+                &mut output,
+            );
+            match count {
+                0 => Ok(()),
+                _ => Err(String::from_utf8_lossy(&output).into_owned()),
+            }
+        },
+    );
+}
+
+#[test]
+fn test_san() {
+    let options = Options {
+        san: true,
+        ..Default::default()
+    };
+    let input = prefix();
+    check_all(
+        |imp| imp.caps.san,
+        |t| check_hash(t, &options, &input, &SAN_HASH),
+    );
+}
+
+// `shakmaty` doesn't support SAN fully, as it sometimes writes both file and rank of the moving
+// piece when rank alone is enough. Check that this is the only difference from the reference.
+#[test]
+fn test_shakmaty_san() {
+    const FEN: &str = "1K1BBNNk/1Q4Rp/3Q4/R4Q2/2Q4Q/4Q3/1Q4Q1/3Q4 w - - 0 1";
+    let options = Options {
+        san: true,
+        big_depth: false,
+        ..Default::default()
+    };
+    let output = |test: &dyn DynTester| {
+        let mut output = Vec::new();
+        test.run_many(&options, &mut FEN.as_bytes(), &mut output);
+        String::from_utf8(output).unwrap()
+    };
+    let expected = output(&impls::mailbox::Test);
+    let actual = output(&impls::shakmaty::Test);
+    let mut differences = 0;
+    for (expected, actual) in expected.lines().zip(actual.lines()) {
+        // Depth dumps also hash SAN, so they are expected to differ.
+        if expected == actual || expected.starts_with("depth-dump-") {
+            continue;
+        }
+        let (uci, san) = expected.trim_start().split_once(' ').unwrap();
+        let with_file = format!("{}{}{}", &san[..1], &uci[..1], &san[1..]);
+        assert_eq!(actual, format!("  {} {}", uci, with_file));
+        differences += 1;
+    }
+    assert_eq!(differences, 16);
+}
+
+#[test]
+fn test_parallel() {
+    let options = Options {
+        san: true,
+        ..Default::default()
+    };
+    let input = prefix();
+    check_all(
+        |imp| imp.caps.san,
+        |t| {
+            let mut hasher = Sha256::default();
+            selftest::run_many_parallel(t, &options, &mut input.as_bytes(), &mut hasher, 3);
+            let hash = hasher.finalize();
+            match hash[..] == SAN_HASH[..] {
+                true => Ok(()),
+                false => Err(format!("output hash mismatch: {:x}", hash)),
+            }
+        },
+    );
+}
+
+#[test]
+fn test_custom_depth_specs() {
+    // The default specs passed explicitly must give the same output.
+    let options = Options {
+        custom_depth_specs: Some(Options::default().depth_specs().into()),
+        ..Default::default()
+    };
+    let input = prefix();
+    check_all(
+        |imp| imp.name == "chess",
+        |t| check_hash(t, &options, &input, &PREFIX_HASH),
+    );
+
+    let specs: Vec<DepthSpec> = ["1", "4-heatmaps"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+    assert_eq!(specs[1].name(), "4-heatmaps");
+    let options = Options {
+        custom_depth_specs: Some(specs.clone().into()),
+        ..Default::default()
+    };
+    let fen = "r1bqk1nr/pppp1ppp/2n5/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4";
+    let reference = impls::chess::Test.report(&options, fen).unwrap();
+    let dumped: Vec<_> = reference.depth_dumps.iter().map(|d| d.spec).collect();
+    assert_eq!(dumped, specs);
+    for imp in ["owlchess", "shakmaty"] {
+        let t = impls::find(imp).unwrap().selftest.unwrap();
+        let report = t.report(&options, fen).unwrap();
+        assert_eq!(report.depth_dumps, reference.depth_dumps, "{}", imp);
+    }
 }
 
 #[test]
-fn test_chess() {
-    run_test(impls::chess::Test);
+fn test_json_output() {
+    let options = Options {
+        big_depth: false,
+        terminal_status: true,
+        format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let input: String = prefix()
+        .lines()
+        .take(30)
+        .map(|l| l.to_string() + "\n")
+        .collect();
+    let mut output = Vec::new();
+    Tester::new(impls::chess::Test, options.clone(), &mut output).run_many(&mut input.as_bytes());
+    let mut sink = io::sink();
+    let mut tester = Tester::new(impls::chess::Test, options, &mut sink);
+    let fens = input
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'));
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), fens.clone().count());
+    for (fen, line) in fens.zip(output.lines()) {
+        let value: Value = serde_json::from_str(line).unwrap();
+        let report = tester.report(fen).unwrap();
+        assert_eq!(value["fen"], fen);
+        assert_eq!(value["moves"], json!(report.moves));
+        assert_eq!(value["check"], report.is_check);
+        assert_eq!(value["insufficient_material"], Value::Null);
+        assert_eq!(value["white_heatmap"], report.heatmaps.unwrap()[0]);
+        let hashes: Vec<_> = report.depth_dumps.iter().map(|d| d.hash).collect();
+        let json_hashes: Vec<_> = value["depth_dumps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["hash"].as_u64().unwrap())
+            .collect();
+        assert_eq!(json_hashes, hashes);
+    }
 }
 
 #[test]
-fn test_owlchess() {
-    run_test(impls::owlchess::Test);
+fn test_invalid_fen() {
+    let mut output = Vec::new();
+    let mut tester = Tester::new(impls::chess::Test, Default::default(), &mut output);
+    tester.run_many(&mut "invalid fen\n".as_bytes());
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("fen: invalid fen\nerror: chess: invalid fen"));
 }